@@ -0,0 +1,165 @@
+//! Minimal science application: sums a series while honoring control requests, checkpointing its progress and
+//! reporting status to the client.
+
+use boinc_app_api::{
+    app::{AppClient, ExitStatus},
+    control_loop::*,
+    init_data::*,
+    models::*,
+    shmem::*,
+};
+use std::{cell::RefCell, fs, path::Path, process, time::Duration};
+
+const CHECKPOINT_FILE: &str = "checkpoint.txt";
+const TOTAL_STEPS: u64 = 10_000_000;
+const STEPS_PER_CALL: u64 = 10_000;
+
+#[derive(Debug, Default, PartialEq)]
+struct Work {
+    step: u64,
+    sum: f64,
+    cpu_time: f64,
+}
+
+impl Work {
+    fn load(path: &Path) -> Option<Self> {
+        let s = fs::read_to_string(path).ok()?;
+        let mut it = s.split_whitespace();
+        Some(Self {
+            step: it.next()?.parse().ok()?,
+            sum: it.next()?.parse().ok()?,
+            cpu_time: it.next()?.parse().ok()?,
+        })
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(
+            &tmp,
+            format!("{} {} {}", self.step, self.sum, self.cpu_time),
+        )?;
+        fs::rename(&tmp, path)
+    }
+}
+
+fn run(
+    app_channel: SharedAppChannel,
+    mode: ChannelMode,
+    init: &AppInitData,
+    checkpoint_path: &Path,
+    total_steps: u64,
+) -> LoopExit {
+    let work = RefCell::new(Work::load(checkpoint_path).unwrap_or_default());
+
+    let checkpoint = {
+        let w = work.borrow();
+        CheckpointState {
+            cpu_time: w.cpu_time,
+            fraction_done: w.step as f64 / total_steps as f64,
+        }
+    };
+    let initial = AppStatusData::initial(init, Some(&checkpoint));
+    app_channel.force(Message::Status(StatusMessage::AppStatus(initial)));
+
+    let client = AppClient::new(app_channel);
+    let heartbeat_timeout = match mode {
+        ChannelMode::Mmap => Some(HEARTBEAT_TIMEOUT),
+        ChannelMode::Standalone => None,
+    };
+
+    let exit = ControlLoop::new(
+        &client,
+        || {
+            let mut w = work.borrow_mut();
+            let end = (w.step + STEPS_PER_CALL).min(total_steps);
+            for i in w.step..end {
+                w.sum += 1.0 / ((i + 1) as f64 * (i + 1) as f64);
+            }
+            w.step = end;
+            if end == total_steps {
+                WorkStatus::Done
+            } else {
+                WorkStatus::Continue
+            }
+        },
+        || {
+            let mut w = work.borrow_mut();
+            w.cpu_time = boinc_app_api::usage::process_cpu_time().unwrap_or(w.cpu_time);
+            if let Err(e) = w.save(checkpoint_path) {
+                eprintln!("Failed to write checkpoint: {}", e);
+            }
+        },
+        || work.borrow().step as f64 / total_steps as f64,
+    )
+    .checkpoint_period(init.checkpoint_period.unwrap_or(Duration::from_secs(60)))
+    .status_period(
+        init.fraction_done_update_period
+            .unwrap_or(Duration::from_secs(1)),
+    )
+    .heartbeat_timeout(heartbeat_timeout)
+    .run();
+
+    client.finish(exit_status(exit));
+    if exit == LoopExit::Finished {
+        println!("sum = {}", work.borrow().sum);
+    }
+    exit
+}
+
+fn exit_status(exit: LoopExit) -> ExitStatus {
+    match exit {
+        LoopExit::Finished | LoopExit::Quit => ExitStatus::Success,
+        LoopExit::Aborted => ExitStatus::Aborted,
+        LoopExit::NoHeartbeat | LoopExit::Corrupted => ExitStatus::Error(1),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let init = match fs::File::open(INIT_DATA_FILE) {
+        Ok(f) => AppInitData::from_reader(f)?,
+        Err(_) => AppInitData::default(),
+    };
+    let (app_channel, mode) = open_app_channel()?;
+    eprintln!("Connected to the client: {:?}", mode);
+
+    let exit = run(
+        app_channel,
+        mode,
+        &init,
+        &init.checkpoint_path(CHECKPOINT_FILE),
+        TOTAL_STEPS,
+    );
+    match exit_status(exit) {
+        ExitStatus::Success => Ok(()),
+        status => process::exit(status.into_raw()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_smoke() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let checkpoint_path = tmp.path().join(CHECKPOINT_FILE);
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+
+        let exit = run(
+            app_channel.clone(),
+            ChannelMode::Standalone,
+            &AppInitData::default(),
+            &checkpoint_path,
+            100_000,
+        );
+
+        assert_eq!(exit, LoopExit::Finished);
+        assert_eq!(Work::load(&checkpoint_path).unwrap().step, 100_000);
+        assert!(app_channel.is_empty(MsgChannel::ProcessControlReply));
+        match app_channel.pull_status() {
+            Some(StatusMessage::AppStatus(v)) => assert_eq!(v.fraction_done, 1.0),
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Compares a single-lock channel with a per-channel-lock one while two threads use different channels.
+//!
+//! Run with `cargo run --release --example channel_contention`.
+
+use boinc_app_api::{models::MsgChannel, shmem::*};
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+const OPS_PER_THREAD: usize = 1_000_000;
+
+fn hammer(app_channel: SharedAppChannel) -> Duration {
+    let start = Instant::now();
+    let workers: Vec<_> = [
+        (MsgChannel::Heartbeat, "<wss>1</wss>"),
+        (MsgChannel::AppStatus, "<fraction_done>0.5</fraction_done>"),
+    ]
+    .iter()
+    .map(|&(c, payload)| {
+        let app_channel = app_channel.clone();
+        thread::spawn(move || {
+            for _ in 0..OPS_PER_THREAD {
+                unsafe { app_channel.force_unchecked((c, payload.into())) };
+                app_channel.receive(c);
+            }
+        })
+    })
+    .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let single = hammer(Arc::new(MemoryAppChannel::default()));
+    let sharded = hammer(Arc::new(ShardedAppChannel::default()));
+
+    println!("MemoryAppChannel:  {:?}", single);
+    println!("ShardedAppChannel: {:?}", sharded);
+}
@@ -0,0 +1,18 @@
+#![no_main]
+
+use boinc_app_api::{init_data::AppInitData, models::*};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte selects the channel, the rest is the payload. The whole input is also parsed as init data.
+fuzz_target!(|data: &[u8]| {
+    let _ = AppInitData::from_reader(data);
+    if let Some((selector, payload)) = data.split_first() {
+        let selector = *selector as usize;
+        if let Some(c) = ControlMsgChannel::enum_iter().nth(selector % 4) {
+            let _ = ControlMessage::from_raw(c, payload.to_vec());
+        }
+        if let Some(c) = StatusMsgChannel::enum_iter().nth(selector % 4) {
+            let _ = StatusMessage::from_raw(c, payload.to_vec());
+        }
+    }
+});
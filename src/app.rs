@@ -0,0 +1,750 @@
+use crate::{
+    guards::CriticalSection,
+    init_data::AppInitData,
+    models::*,
+    shmem::*,
+    usage::{CpuTime, CpuTimeTracker, MemoryReporter},
+};
+use std::{
+    fs, io,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// File in the slot directory asking the client to restart the task later, see `AppClient::temporary_exit`.
+pub const TEMPORARY_EXIT_FILE: &str = "boinc_temporary_exit";
+
+/// Control state of the application as requested by the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BoincStatus {
+    pub suspended: bool,
+    /// Network activity is suspended. Independent of `suspended`, computation may continue.
+    pub network_suspended: bool,
+    pub quit_request: bool,
+    pub abort_request: bool,
+}
+
+/// How the application ends, passed to `AppClient::finish`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success,
+    /// A child process of a wrapper application failed with the given status. Reported as `EXIT_CHILD_FAILED`.
+    ChildFailed(i32),
+    /// The application gave up because the client asked it to abort (`EXIT_ABORTED_BY_CLIENT`).
+    Aborted,
+    /// Any other failure, reported with the given code. A code of 0 is reported as 1 so it is never mistaken for
+    /// success.
+    Error(i32),
+}
+
+impl ExitStatus {
+    /// `EXIT_ABORTED_BY_CLIENT` from BOINC's `error_numbers.h`.
+    pub const ABORTED_BY_CLIENT: i32 = 194;
+    /// `EXIT_CHILD_FAILED` from BOINC's `error_numbers.h`.
+    pub const CHILD_FAILED: i32 = 195;
+
+    /// Exit code the process is expected to exit with. The status of a failed child is not part of it, so
+    /// converting back gives `ChildFailed(CHILD_FAILED)`.
+    pub fn into_raw(self) -> i32 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::ChildFailed(_) => Self::CHILD_FAILED,
+            ExitStatus::Aborted => Self::ABORTED_BY_CLIENT,
+            ExitStatus::Error(0) => 1,
+            ExitStatus::Error(code) => code,
+        }
+    }
+}
+
+impl From<i32> for ExitStatus {
+    fn from(code: i32) -> Self {
+        match code {
+            0 => ExitStatus::Success,
+            Self::ABORTED_BY_CLIENT => ExitStatus::Aborted,
+            Self::CHILD_FAILED => ExitStatus::ChildFailed(Self::CHILD_FAILED),
+            code => ExitStatus::Error(code),
+        }
+    }
+}
+
+struct AppState {
+    status: BoincStatus,
+    critical_sections: usize,
+    last_heartbeat: Instant,
+    checkpoint_cpu_time: f64,
+    fraction_done: f64,
+    bytes_sent: Option<f64>,
+    bytes_received: Option<f64>,
+    /// First progress report of this episode, the base for the ETA estimate.
+    progress_start: Option<(Instant, f64)>,
+    monotonic_progress: bool,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            status: Default::default(),
+            critical_sections: 0,
+            last_heartbeat: Instant::now(),
+            checkpoint_cpu_time: 0.0,
+            fraction_done: 0.0,
+            bytes_sent: None,
+            bytes_received: None,
+            progress_start: None,
+            monotonic_progress: false,
+        }
+    }
+}
+
+impl AppState {
+    fn apply(&mut self, m: ControlMessage) {
+        match m {
+            ControlMessage::ProcessControlRequest(r) => match r {
+                ProcessControlRequest::Quit => self.status.quit_request = true,
+                ProcessControlRequest::Suspend { .. } => self.status.suspended = true,
+                ProcessControlRequest::Resume => self.status.suspended = false,
+                ProcessControlRequest::SuspendNetwork => self.status.network_suspended = true,
+                ProcessControlRequest::ResumeNetwork => self.status.network_suspended = false,
+                ProcessControlRequest::Abort { .. } => self.status.abort_request = true,
+            },
+            ControlMessage::Heartbeat(_) => self.last_heartbeat = Instant::now(),
+            _ => {}
+        }
+    }
+
+    fn effective_status(&self) -> BoincStatus {
+        let mut status = self.status;
+        if self.critical_sections > 0 {
+            status.suspended = false;
+            status.quit_request = false;
+        }
+        status
+    }
+}
+
+/// Application side of the connection with the BOINC client.
+pub struct AppClient {
+    app_channel: SharedAppChannel,
+    state: Mutex<AppState>,
+    memory: MemoryReporter,
+    cpu: CpuTimeTracker,
+    /// Start of the elapsed time reported to the client.
+    started: Instant,
+}
+
+impl AppClient {
+    pub fn new(app_channel: SharedAppChannel) -> Self {
+        Self {
+            app_channel,
+            state: Default::default(),
+            memory: MemoryReporter::new(),
+            cpu: CpuTimeTracker::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn app_channel(&self) -> &SharedAppChannel {
+        &self.app_channel
+    }
+
+    /// Process pending control messages and return the current status.
+    ///
+    /// Suspend and quit requests are not reported while a critical section is active. Corrupted shared memory is
+    /// reported instead of decoding messages from it.
+    pub fn get_status(&self) -> Result<BoincStatus, Corrupted> {
+        self.app_channel.validate()?;
+        let mut state = self.state.lock().unwrap();
+        while let Some(m) = self.app_channel.pull_control() {
+            state.apply(m);
+        }
+        Ok(state.effective_status())
+    }
+
+    /// Time since the last heartbeat from the client, or since the client was created if none arrived yet.
+    pub fn since_heartbeat(&self) -> Duration {
+        self.state.lock().unwrap().last_heartbeat.elapsed()
+    }
+
+    /// CPU time as included in status reports. Approximated by wall-clock time where `getrusage` fails.
+    pub fn cpu_time(&self) -> CpuTime {
+        self.cpu.cpu_time()
+    }
+
+    /// Record that the application has just written a checkpoint.
+    pub fn checkpoint_completed(&self) {
+        self.state.lock().unwrap().checkpoint_cpu_time = self.cpu.cpu_time().seconds;
+    }
+
+    /// Count network traffic done by the application itself. The totals are included in every following report.
+    pub fn record_traffic(&self, sent: f64, received: f64) {
+        let mut state = self.state.lock().unwrap();
+        *state.bytes_sent.get_or_insert(0.0) += sent;
+        *state.bytes_received.get_or_insert(0.0) += received;
+    }
+
+    /// Never report less progress than already reported in this session, e.g. when the application restarts
+    /// from an older checkpoint. Lower values are replaced by the highest one reported so far.
+    pub fn set_monotonic_progress(&self, enabled: bool) {
+        self.state.lock().unwrap().monotonic_progress = enabled;
+    }
+
+    fn app_status(&self, fraction_done: f64) -> AppStatusData {
+        let mut state = self.state.lock().unwrap();
+        let fraction_done = clamp_fraction(fraction_done);
+        let fraction_done = if state.monotonic_progress {
+            fraction_done.max(state.fraction_done)
+        } else {
+            fraction_done
+        };
+        state.fraction_done = fraction_done;
+        AppStatusData {
+            current_cpu_time: self.cpu.cpu_time().seconds,
+            checkpoint_cpu_time: state.checkpoint_cpu_time,
+            want_network: false,
+            fraction_done,
+            other_pid: None,
+            bytes_sent: state.bytes_sent,
+            bytes_received: state.bytes_received,
+            working_set_size: self.memory.working_set_size().ok(),
+            elapsed_time: Some(self.started.elapsed().as_secs_f64()),
+        }
+    }
+
+    /// Report progress to the client, clamped to `0..=1` like `AppChannel::report_fraction_done`. Returns `false`
+    /// if the client has not consumed the previous report yet.
+    pub fn report_status(&self, fraction_done: f64) -> bool {
+        let status = self.app_status(fraction_done);
+        self.app_channel
+            .push(Message::Status(StatusMessage::AppStatus(status)))
+            .is_none()
+    }
+
+    /// Report progress like `report_status`, replacing a previous report the client has not consumed yet.
+    pub fn force_status(&self, fraction_done: f64) {
+        let status = self.app_status(fraction_done);
+        self.app_channel
+            .force(Message::Status(StatusMessage::AppStatus(status)));
+    }
+
+    /// Report progress like `report_status` and estimate the remaining time from the average rate of progress
+    /// since the first call. The status message has no field for it, so the estimate is only returned.
+    pub fn report_progress(&self, fraction_done: f64) -> Option<Duration> {
+        self.report_status(fraction_done);
+        self.estimate_remaining(fraction_done, Instant::now())
+    }
+
+    /// Report progress like `report_status` and estimate the remaining time of a task estimated at `fpops_est`
+    /// operations from a rate measured with `Benchmark::run`.
+    #[cfg(feature = "benchmark")]
+    pub fn report_progress_at_rate(
+        &self,
+        fraction_done: f64,
+        fpops_per_sec: f64,
+        fpops_est: f64,
+    ) -> Option<Duration> {
+        self.report_status(fraction_done);
+        crate::benchmark::Benchmark::estimate_remaining(
+            fpops_per_sec,
+            fpops_est,
+            clamp_fraction(fraction_done),
+        )
+    }
+
+    fn estimate_remaining(&self, fraction_done: f64, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let (start, start_fraction) = *state.progress_start.get_or_insert((now, fraction_done));
+        let elapsed = now.saturating_duration_since(start).as_secs_f64();
+        let rate = (fraction_done - start_fraction) / elapsed;
+        if rate.is_nan() || rate <= 0.0 {
+            return None;
+        }
+        Duration::try_from_secs_f64((1.0 - fraction_done).max(0.0) / rate).ok()
+    }
+
+    /// Write the final status and acknowledge a handled quit or abort request. The client learns about any other
+    /// outcome from the exit code alone, which is returned for the caller to exit with.
+    pub fn finish(&self, exit_status: ExitStatus) -> i32 {
+        let fraction_done = self.state.lock().unwrap().fraction_done;
+        self.force_status(fraction_done);
+
+        let requested = self.state.lock().unwrap().status;
+        let reply = if requested.quit_request {
+            Some(ProcessControlReply::Quit)
+        } else if requested.abort_request {
+            Some(ProcessControlReply::Abort)
+        } else {
+            None
+        };
+        if let Some(reply) = reply {
+            self.app_channel
+                .force(Message::Status(StatusMessage::ProcessControlReply(reply)));
+        }
+        exit_status.into_raw()
+    }
+
+    /// Write the final status and ask the client to restart the task after `delay` instead of treating the exit
+    /// as a failure, like `boinc_temporary_exit`. The request is the `boinc_temporary_exit` file in the slot
+    /// directory, holding the delay in whole seconds, the reason and a `notice` line if `is_notice` asks the client
+    /// to show the reason to the user. The caller is expected to exit with the returned status.
+    pub fn temporary_exit(
+        &self,
+        init: &AppInitData,
+        delay: Duration,
+        reason: &str,
+        is_notice: bool,
+    ) -> io::Result<ExitStatus> {
+        let fraction_done = self.state.lock().unwrap().fraction_done;
+        self.force_status(fraction_done);
+
+        // The file is line based, so the reason must stay on one line.
+        let mut contents = format!("{}\n{}\n", delay.as_secs(), reason.replace('\n', " "));
+        if is_notice {
+            contents.push_str("notice\n");
+        }
+        fs::write(init.checkpoint_path(TEMPORARY_EXIT_FILE), contents)?;
+        Ok(ExitStatus::Success)
+    }
+
+    /// Report the computation as complete and `finish` successfully.
+    pub fn report_done(&self) {
+        self.state.lock().unwrap().fraction_done = 1.0;
+        self.finish(ExitStatus::Success);
+    }
+
+    /// Enter a critical section which lasts until the returned guard is dropped.
+    pub fn begin_critical_section(&self) -> CriticalSection<'_> {
+        CriticalSection::new(self)
+    }
+
+    pub(crate) fn enter_critical_section(&self) {
+        self.state.lock().unwrap().critical_sections += 1;
+    }
+
+    pub(crate) fn leave_critical_section(&self) {
+        self.state.lock().unwrap().critical_sections -= 1;
+    }
+}
+
+/// Default minimum time between status reports, matching the client's `fraction_done_update_period`.
+pub const DEFAULT_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes status reports, skipping the ones that come too soon after the previous write.
+pub struct AppStatusReporter {
+    app_channel: SharedAppChannel,
+    min_interval: Duration,
+    last_write: Option<Instant>,
+}
+
+impl AppStatusReporter {
+    pub fn new(app_channel: SharedAppChannel) -> Self {
+        Self {
+            app_channel,
+            min_interval: DEFAULT_STATUS_INTERVAL,
+            last_write: None,
+        }
+    }
+
+    pub fn min_interval(mut self, v: Duration) -> Self {
+        self.min_interval = v;
+        self
+    }
+
+    /// Write the status unless the previous write was too recent or has not been consumed yet.
+    /// Returns whether the status was written.
+    pub fn report(&mut self, status: AppStatusData) -> bool {
+        self.report_at(status, Instant::now(), false)
+    }
+
+    /// Write the status regardless of the interval, replacing an unconsumed report.
+    pub fn report_forced(&mut self, status: AppStatusData) -> bool {
+        self.report_at(status, Instant::now(), true)
+    }
+
+    fn report_at(&mut self, status: AppStatusData, now: Instant, force: bool) -> bool {
+        let m = Message::Status(StatusMessage::AppStatus(status));
+        let written = if force {
+            self.app_channel.force(m);
+            true
+        } else if self
+            .last_write
+            .is_some_and(|t| now.saturating_duration_since(t) < self.min_interval)
+        {
+            false
+        } else {
+            self.app_channel.push(m).is_none()
+        };
+
+        if written {
+            self.last_write = Some(now);
+        }
+        written
+    }
+}
+
+/// Combines the progress of sequential sub-tasks, e.g. of a wrapper app, into the overall fraction done.
+pub struct ProgressAggregator {
+    weights: Vec<f64>,
+    fractions: Vec<f64>,
+}
+
+impl ProgressAggregator {
+    /// One sub-task per weight. Weights are relative, e.g. the expected run time of each sub-task.
+    pub fn new(weights: &[f64]) -> Self {
+        let total: f64 = weights.iter().map(|w| w.max(0.0)).sum();
+        let weights: Vec<f64> = weights
+            .iter()
+            .map(|w| {
+                if total > 0.0 {
+                    w.max(0.0) / total
+                } else {
+                    1.0 / weights.len() as f64
+                }
+            })
+            .collect();
+        Self {
+            fractions: vec![0.0; weights.len()],
+            weights,
+        }
+    }
+
+    /// Set the fraction done of sub-task `i`, clamped to `0..=1`. Returns `false` if there is no such sub-task.
+    pub fn set(&mut self, i: usize, fraction_done: f64) -> bool {
+        match self.fractions.get_mut(i) {
+            Some(f) => {
+                *f = clamp_fraction(fraction_done);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Overall fraction done, the weighted sum of the sub-task fractions.
+    pub fn fraction_done(&self) -> f64 {
+        let v: f64 = self
+            .weights
+            .iter()
+            .zip(&self.fractions)
+            .map(|(w, f)| w * f)
+            .sum();
+        v.min(1.0)
+    }
+
+    /// Report the overall fraction with `AppChannel::report_fraction_done`.
+    pub fn report(&self, app_channel: &SharedAppChannel) -> bool {
+        app_channel.report_fraction_done(self.fraction_done())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::CpuTimeSource;
+    use std::{io, sync::Arc, thread};
+
+    #[test]
+    fn test_suspend_deferred_in_critical_section() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        let guard = client.begin_critical_section();
+        app_channel.force(Message::Control(ControlMessage::ProcessControlRequest(
+            ProcessControlRequest::Suspend {
+                reason: SuspendReason::empty(),
+            },
+        )));
+        assert!(!client.get_status().unwrap().suspended);
+
+        drop(guard);
+        assert!(client.get_status().unwrap().suspended);
+    }
+
+    #[test]
+    fn test_network_suspend_independent_of_suspend() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        app_channel.force(Message::Control(ControlMessage::ProcessControlRequest(
+            ProcessControlRequest::SuspendNetwork,
+        )));
+        let status = client.get_status().unwrap();
+        assert!(status.network_suspended);
+        assert!(!status.suspended);
+
+        app_channel.force(Message::Control(ControlMessage::ProcessControlRequest(
+            ProcessControlRequest::ResumeNetwork,
+        )));
+        assert!(!client.get_status().unwrap().network_suspended);
+    }
+
+    #[test]
+    fn test_finish_acknowledges_quit() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        app_channel.force(Message::Control(ControlMessage::ProcessControlRequest(
+            ProcessControlRequest::Quit,
+        )));
+        assert!(client.get_status().unwrap().quit_request);
+
+        client.finish(ExitStatus::Success);
+        assert_eq!(
+            app_channel.peek(MsgChannel::ProcessControlReply),
+            Some(b"<quit/>".to_vec())
+        );
+        assert!(!app_channel.is_empty(MsgChannel::AppStatus));
+    }
+
+    #[test]
+    fn test_finish_without_request_sends_no_reply() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        assert_eq!(client.finish(ExitStatus::Error(3)), 3);
+        assert!(!app_channel.is_empty(MsgChannel::AppStatus));
+        assert!(app_channel.is_empty(MsgChannel::ProcessControlReply));
+    }
+
+    #[test]
+    fn test_temporary_exit() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let init = AppInitData {
+            boinc_dir: Some(tmp.path().to_path_buf()),
+            slot: Some(0),
+            ..Default::default()
+        };
+        let slot_dir = init.slot_dir().unwrap();
+        std::fs::create_dir_all(&slot_dir).unwrap();
+
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        let status = client
+            .temporary_exit(&init, Duration::from_secs(600), "GPU busy\nand hot", true)
+            .unwrap();
+        assert_eq!(status.into_raw(), 0);
+        assert!(!app_channel.is_empty(MsgChannel::AppStatus));
+        assert!(app_channel.is_empty(MsgChannel::ProcessControlReply));
+        assert_eq!(
+            std::fs::read_to_string(slot_dir.join(TEMPORARY_EXIT_FILE)).unwrap(),
+            "600\nGPU busy and hot\nnotice\n"
+        );
+
+        client
+            .temporary_exit(&init, Duration::from_millis(1500), "later", false)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(slot_dir.join(TEMPORARY_EXIT_FILE)).unwrap(),
+            "1\nlater\n"
+        );
+    }
+
+    #[test]
+    fn test_exit_status_codes() {
+        assert_eq!(ExitStatus::Success.into_raw(), 0);
+        assert_eq!(ExitStatus::ChildFailed(1).into_raw(), 195);
+        assert_eq!(ExitStatus::Aborted.into_raw(), 194);
+        assert_eq!(ExitStatus::Error(3).into_raw(), 3);
+        assert_eq!(ExitStatus::Error(0).into_raw(), 1);
+
+        assert_eq!(ExitStatus::from(0), ExitStatus::Success);
+        assert_eq!(ExitStatus::from(194), ExitStatus::Aborted);
+        assert_eq!(ExitStatus::from(195), ExitStatus::ChildFailed(195));
+        assert_eq!(ExitStatus::from(7), ExitStatus::Error(7));
+        assert_eq!(
+            ExitStatus::from(ExitStatus::ChildFailed(1).into_raw()),
+            ExitStatus::ChildFailed(195)
+        );
+    }
+
+    #[test]
+    fn test_report_done() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        assert!(client.report_status(0.75));
+        app_channel.clear(MsgChannel::AppStatus);
+        client.report_done();
+
+        let status = app_channel.receive(MsgChannel::AppStatus).unwrap();
+        match StatusMessage::from_raw(StatusMsgChannel::AppStatus, status).unwrap() {
+            StatusMessage::AppStatus(v) => assert_eq!(v.fraction_done, 1.0),
+            other => panic!("unexpected status {:?}", other),
+        }
+        assert!(app_channel.is_empty(MsgChannel::ProcessControlReply));
+    }
+
+    #[test]
+    fn test_report_status_clamps_fraction() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        for (reported, expected) in [(1.2, 1.0), (-0.5, 0.0), (f64::NAN, 0.0), (0.25, 0.25)] {
+            assert!(client.report_status(reported));
+            match app_channel.pull_status() {
+                Some(StatusMessage::AppStatus(v)) => assert_eq!(v.fraction_done, expected),
+                other => panic!("unexpected status {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_monotonic_progress() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+        let reported = |fraction_done| {
+            assert!(client.report_status(fraction_done));
+            match app_channel.pull_status() {
+                Some(StatusMessage::AppStatus(v)) => v.fraction_done,
+                other => panic!("unexpected status {:?}", other),
+            }
+        };
+
+        assert_eq!(reported(0.5), 0.5);
+        assert_eq!(reported(0.3), 0.3);
+
+        client.set_monotonic_progress(true);
+        assert_eq!(reported(0.5), 0.5);
+        assert_eq!(reported(0.3), 0.5);
+        assert_eq!(reported(0.7), 0.7);
+    }
+
+    #[test]
+    fn test_elapsed_time_independent_of_cpu_time() {
+        struct FixedCpuTime;
+
+        impl CpuTimeSource for FixedCpuTime {
+            fn cpu_time(&self) -> io::Result<f64> {
+                Ok(1.5)
+            }
+        }
+
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let mut client = AppClient::new(app_channel.clone());
+        client.cpu = CpuTimeTracker::with_source(FixedCpuTime);
+        let report = || {
+            assert!(client.report_status(0.5));
+            match app_channel.pull_status() {
+                Some(StatusMessage::AppStatus(v)) => v,
+                other => panic!("unexpected status {:?}", other),
+            }
+        };
+
+        let first = report();
+        thread::sleep(Duration::from_millis(50));
+        let second = report();
+        assert_eq!(first.current_cpu_time, second.current_cpu_time);
+        assert!(second.elapsed_time.unwrap() - first.elapsed_time.unwrap() >= 0.05);
+    }
+
+    #[test]
+    fn test_report_includes_traffic() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        client.record_traffic(10.0, 20.0);
+        client.record_traffic(5.0, 0.0);
+        assert!(client.report_status(0.5));
+
+        match app_channel.pull_status() {
+            Some(StatusMessage::AppStatus(v)) => {
+                assert_eq!(v.bytes_sent, Some(15.0));
+                assert_eq!(v.bytes_received, Some(20.0));
+            }
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_progress_eta_decreases() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel);
+
+        let start = Instant::now();
+        assert_eq!(client.estimate_remaining(0.0, start), None);
+
+        let mut last = None;
+        for i in 1..=5 {
+            let now = start + Duration::from_secs(i * 10);
+            let eta = client.estimate_remaining(i as f64 * 0.1, now).unwrap();
+            if let Some(last) = last {
+                assert!(eta < last);
+            }
+            last = Some(eta);
+        }
+        // 10% per 10 s leaves 50 s for the remaining half.
+        assert_eq!(last, Some(Duration::from_secs(50)));
+    }
+
+    #[cfg(feature = "benchmark")]
+    #[test]
+    fn test_progress_at_rate() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        assert_eq!(
+            client.report_progress_at_rate(0.25, 1e9, 1e12),
+            Some(Duration::from_secs(750))
+        );
+        assert_eq!(client.report_progress_at_rate(0.5, 0.0, 1e12), None);
+        match app_channel.pull_status() {
+            Some(StatusMessage::AppStatus(v)) => assert_eq!(v.fraction_done, 0.25),
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_progress_aggregated_by_weight() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let mut progress = ProgressAggregator::new(&[1.0, 3.0]);
+        assert_eq!(progress.fraction_done(), 0.0);
+
+        assert!(progress.set(0, 1.0));
+        assert_eq!(progress.fraction_done(), 0.25);
+        assert!(progress.set(1, 0.5));
+        assert_eq!(progress.fraction_done(), 0.625);
+        assert!(!progress.set(2, 1.0));
+        assert_eq!(progress.fraction_done(), 0.625);
+
+        assert!(progress.report(&app_channel));
+        match app_channel.pull_status() {
+            Some(StatusMessage::AppStatus(v)) => assert_eq!(v.fraction_done, 0.625),
+            other => panic!("unexpected status {:?}", other),
+        }
+
+        assert!(progress.set(1, 2.0));
+        assert_eq!(progress.fraction_done(), 1.0);
+        assert!(progress.set(1, f64::NAN));
+        assert_eq!(progress.fraction_done(), 0.25);
+    }
+
+    #[test]
+    fn test_status_reports_throttled() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let mut reporter = AppStatusReporter::new(app_channel.clone());
+
+        let status = AppStatusData {
+            current_cpu_time: 1.0,
+            checkpoint_cpu_time: 0.0,
+            want_network: false,
+            fraction_done: 0.5,
+            other_pid: None,
+            bytes_sent: None,
+            bytes_received: None,
+            working_set_size: None,
+            elapsed_time: None,
+        };
+
+        let start = Instant::now();
+        let mut written = vec![];
+        for ms in [0, 300, 600, 1000, 1200, 2100] {
+            let now = start + Duration::from_millis(ms);
+            if reporter.report_at(status.clone(), now, false) {
+                written.push(ms);
+            }
+            app_channel.clear(MsgChannel::AppStatus);
+        }
+        assert_eq!(written, vec![0, 1000, 2100]);
+
+        assert!(reporter.report_forced(status));
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+/// Estimates the floating point throughput of the host by timing a fixed dummy workload.
+pub struct Benchmark {
+    ops: u64,
+}
+
+impl Default for Benchmark {
+    fn default() -> Self {
+        Self::new(10_000_000)
+    }
+}
+
+impl Benchmark {
+    pub fn new(ops: u64) -> Self {
+        Self { ops }
+    }
+
+    /// Run the workload and return the estimated rate in floating point operations per second.
+    pub fn run(&self) -> f64 {
+        let start = Instant::now();
+        let mut x = 1.0f64;
+        // Two floating point operations per iteration.
+        for _ in 0..self.ops / 2 {
+            x = std::hint::black_box(x * 1.000_000_1 + 0.000_000_1);
+        }
+        std::hint::black_box(x);
+        self.ops as f64 / start.elapsed().as_secs_f64().max(1e-9)
+    }
+
+    /// Time left for a task estimated at `fpops_est` operations which is `fraction_done` complete. `None` unless
+    /// the rate is positive and the estimate is a finite non-negative number.
+    pub fn estimate_remaining(
+        fpops_per_sec: f64,
+        fpops_est: f64,
+        fraction_done: f64,
+    ) -> Option<Duration> {
+        if fpops_per_sec.is_nan() || fpops_per_sec <= 0.0 {
+            return None;
+        }
+        let remaining = fpops_est * (1.0 - fraction_done.clamp(0.0, 1.0));
+        Duration::try_from_secs_f64(remaining / fpops_per_sec).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_positive_and_stable() {
+        let b = Benchmark::default();
+        let first = b.run();
+        let second = b.run();
+
+        assert!(first > 0.0);
+        assert!(second > 0.0);
+        assert!(first / second < 3.0 && second / first < 3.0);
+    }
+
+    #[test]
+    fn test_estimate_remaining() {
+        assert_eq!(
+            Benchmark::estimate_remaining(1e9, 1e12, 0.5),
+            Some(Duration::from_secs(500))
+        );
+    }
+
+    #[test]
+    fn test_estimate_remaining_invalid() {
+        assert_eq!(Benchmark::estimate_remaining(0.0, 1e12, 0.5), None);
+        assert_eq!(Benchmark::estimate_remaining(-1e9, 1e12, 0.5), None);
+        assert_eq!(Benchmark::estimate_remaining(f64::NAN, 1e12, 0.5), None);
+        assert_eq!(Benchmark::estimate_remaining(1e9, -1e12, 0.5), None);
+        assert_eq!(Benchmark::estimate_remaining(1e9, f64::NAN, 0.5), None);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{models::MsgChannel, shmem::*};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+};
+
+fn fingerprints(mem: &SHARED_MEM) -> Vec<u64> {
+    MsgChannel::enum_iter()
+        .map(|c| {
+            let ch = mem.get_channel(c);
+            let mut h = DefaultHasher::new();
+            ch.is_empty().hash(&mut h);
+            ch.as_bytes().hash(&mut h);
+            h.finish()
+        })
+        .collect()
+}
+
+/// Reports which channels changed between polls, for loops that only act on new messages.
+///
+/// Channels are compared by a hash of their occupancy and payload, so a message replaced by an identical one
+/// between two polls is not reported.
+pub struct ChangeTracker {
+    app_channel: SharedAppChannel,
+    last: Mutex<Vec<u64>>,
+}
+
+impl ChangeTracker {
+    /// Start tracking from the current contents.
+    pub fn new(app_channel: SharedAppChannel) -> Self {
+        let last = app_channel.observe(fingerprints);
+        Self {
+            app_channel,
+            last: Mutex::new(last),
+        }
+    }
+
+    /// Channels whose contents changed since the previous call, or since the tracker was created.
+    pub fn poll_changes(&self) -> Vec<MsgChannel> {
+        let current = self.app_channel.observe(fingerprints);
+        let mut last = self.last.lock().unwrap();
+        let changed = MsgChannel::enum_iter()
+            .zip(current.iter().zip(last.iter()))
+            .filter(|(_, (a, b))| a != b)
+            .map(|(c, _)| c)
+            .collect();
+        *last = current;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_only_written_channel_reported() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        app_channel.force(Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        )));
+        let tracker = ChangeTracker::new(app_channel.clone());
+        assert_eq!(tracker.poll_changes(), vec![]);
+
+        app_channel.force(Message::Control(ControlMessage::Heartbeat(Heartbeat {
+            wss: Some(1.0),
+            max_wss: None,
+        })));
+        assert_eq!(tracker.poll_changes(), vec![MsgChannel::Heartbeat]);
+        assert_eq!(tracker.poll_changes(), vec![]);
+
+        app_channel.clear(MsgChannel::Heartbeat);
+        assert_eq!(tracker.poll_changes(), vec![MsgChannel::Heartbeat]);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{app::AppClient, shmem::MIN_POLL_INTERVAL};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the application keeps running without a heartbeat from the client.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of a single unit of work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkStatus {
+    Continue,
+    Done,
+}
+
+/// Reason the control loop has stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopExit {
+    Finished,
+    Quit,
+    Aborted,
+    NoHeartbeat,
+    /// Shared memory no longer holds anything the client could have written.
+    Corrupted,
+}
+
+struct Timer<'a> {
+    period: Duration,
+    last: Instant,
+    callback: Box<dyn FnMut() + 'a>,
+}
+
+/// Standard application main loop: honors control requests, checkpoints periodically and reports progress.
+pub struct ControlLoop<'a> {
+    client: &'a AppClient,
+    do_work: Box<dyn FnMut() -> WorkStatus + 'a>,
+    on_checkpoint: Box<dyn FnMut() + 'a>,
+    progress: Box<dyn FnMut() -> f64 + 'a>,
+    poll_interval: Duration,
+    checkpoint_period: Duration,
+    status_period: Duration,
+    heartbeat_timeout: Option<Duration>,
+    timers: Vec<Timer<'a>>,
+}
+
+impl<'a> ControlLoop<'a> {
+    /// `do_work` performs a short unit of work, `on_checkpoint` saves the state and `progress` returns the fraction done.
+    pub fn new<W, C, P>(client: &'a AppClient, do_work: W, on_checkpoint: C, progress: P) -> Self
+    where
+        W: FnMut() -> WorkStatus + 'a,
+        C: FnMut() + 'a,
+        P: FnMut() -> f64 + 'a,
+    {
+        Self {
+            client,
+            do_work: Box::new(do_work),
+            on_checkpoint: Box::new(on_checkpoint),
+            progress: Box::new(progress),
+            poll_interval: Duration::from_millis(100),
+            checkpoint_period: Duration::from_secs(60),
+            status_period: Duration::from_secs(1),
+            heartbeat_timeout: Some(HEARTBEAT_TIMEOUT),
+            timers: Vec::new(),
+        }
+    }
+
+    /// How often control messages are checked while suspended, at least `MIN_POLL_INTERVAL`.
+    pub fn poll_interval(mut self, v: Duration) -> Self {
+        self.poll_interval = v;
+        self
+    }
+
+    pub fn checkpoint_period(mut self, v: Duration) -> Self {
+        self.checkpoint_period = v;
+        self
+    }
+
+    pub fn status_period(mut self, v: Duration) -> Self {
+        self.status_period = v;
+        self
+    }
+
+    /// Set to `None` when running standalone, without a client sending heartbeats.
+    pub fn heartbeat_timeout(mut self, v: Option<Duration>) -> Self {
+        self.heartbeat_timeout = v;
+        self
+    }
+
+    /// Call `f` every `period` from the loop itself, also while suspended. Calls are only as timely as the
+    /// units of work and the poll interval allow.
+    pub fn on_interval<F>(mut self, period: Duration, f: F) -> Self
+    where
+        F: FnMut() + 'a,
+    {
+        self.timers.push(Timer {
+            period,
+            last: Instant::now(),
+            callback: Box::new(f),
+        });
+        self
+    }
+
+    /// Call the interval callbacks that are due at `now`. A callback that missed several periods is called once.
+    fn fire_timers(&mut self, now: Instant) {
+        for timer in &mut self.timers {
+            if now.saturating_duration_since(timer.last) >= timer.period {
+                (timer.callback)();
+                timer.last = now;
+            }
+        }
+    }
+
+    pub fn run(mut self) -> LoopExit {
+        let mut last_checkpoint = Instant::now();
+        let mut last_report: Option<Instant> = None;
+
+        loop {
+            let status = match self.client.get_status() {
+                Ok(status) => status,
+                Err(_) => return LoopExit::Corrupted,
+            };
+            if status.abort_request {
+                return LoopExit::Aborted;
+            }
+            if status.quit_request {
+                return LoopExit::Quit;
+            }
+            if let Some(timeout) = self.heartbeat_timeout {
+                if self.client.since_heartbeat() > timeout {
+                    return LoopExit::NoHeartbeat;
+                }
+            }
+            self.fire_timers(Instant::now());
+            if status.suspended {
+                thread::sleep(self.poll_interval.max(MIN_POLL_INTERVAL));
+                continue;
+            }
+
+            let work = (self.do_work)();
+
+            if work == WorkStatus::Done || last_checkpoint.elapsed() >= self.checkpoint_period {
+                let _guard = self.client.begin_critical_section();
+                (self.on_checkpoint)();
+                self.client.checkpoint_completed();
+                last_checkpoint = Instant::now();
+            }
+
+            if work == WorkStatus::Done
+                || last_report.is_none_or(|t| t.elapsed() >= self.status_period)
+            {
+                let fraction_done = (self.progress)();
+                if work == WorkStatus::Done {
+                    // The final report must not be lost behind an unconsumed earlier one.
+                    self.client.force_status(fraction_done);
+                } else {
+                    self.client.report_status(fraction_done);
+                }
+                last_report = Some(Instant::now());
+            }
+
+            if work == WorkStatus::Done {
+                return LoopExit::Finished;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::*, shmem::*};
+    use std::{cell::Cell, sync::Arc};
+
+    #[test]
+    fn test_run_to_completion() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        let steps = Cell::new(0);
+        let checkpoints = Cell::new(0);
+        let exit = ControlLoop::new(
+            &client,
+            || {
+                steps.set(steps.get() + 1);
+                if steps.get() == 100 {
+                    WorkStatus::Done
+                } else {
+                    WorkStatus::Continue
+                }
+            },
+            || checkpoints.set(checkpoints.get() + 1),
+            || steps.get() as f64 / 100.0,
+        )
+        .heartbeat_timeout(None)
+        .run();
+
+        assert_eq!(exit, LoopExit::Finished);
+        assert_eq!(steps.get(), 100);
+        assert_eq!(checkpoints.get(), 1);
+        match app_channel.pull_status() {
+            Some(StatusMessage::AppStatus(v)) => assert_eq!(v.fraction_done, 1.0),
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_interval_callback() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel);
+
+        let fired = Cell::new(0);
+        let mut control_loop = ControlLoop::new(&client, || WorkStatus::Done, || {}, || 0.0)
+            .on_interval(Duration::from_millis(20), || fired.set(fired.get() + 1));
+        let start = control_loop.timers[0].last;
+
+        let ms = Duration::from_millis;
+        for (at, expected) in [(10, 0), (20, 1), (30, 1), (40, 2), (200, 3), (210, 3)] {
+            control_loop.fire_timers(start + ms(at));
+            assert_eq!(fired.get(), expected, "at {} ms", at);
+        }
+    }
+
+    #[test]
+    fn test_interval_callback_from_run() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel);
+
+        let period = Duration::from_millis(20);
+        let started = Instant::now();
+        let fired = Cell::new(0);
+        let exit = ControlLoop::new(
+            &client,
+            || {
+                thread::sleep(Duration::from_millis(1));
+                if fired.get() > 0 && started.elapsed() >= Duration::from_millis(100) {
+                    WorkStatus::Done
+                } else {
+                    WorkStatus::Continue
+                }
+            },
+            || {},
+            || 0.0,
+        )
+        .heartbeat_timeout(None)
+        .on_interval(period, || fired.set(fired.get() + 1))
+        .run();
+
+        assert_eq!(exit, LoopExit::Finished);
+        let max_fired = (started.elapsed().as_secs_f64() / period.as_secs_f64()) as usize;
+        assert!(
+            (1..=max_fired).contains(&fired.get()),
+            "fired {} times, at most {} expected",
+            fired.get(),
+            max_fired
+        );
+    }
+
+    #[test]
+    fn test_quit() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let client = AppClient::new(app_channel.clone());
+
+        let steps = Cell::new(0);
+        let exit = ControlLoop::new(
+            &client,
+            || {
+                steps.set(steps.get() + 1);
+                if steps.get() == 10 {
+                    app_channel.force(Message::Control(ControlMessage::ProcessControlRequest(
+                        ProcessControlRequest::Quit,
+                    )));
+                }
+                WorkStatus::Continue
+            },
+            || {},
+            || 0.0,
+        )
+        .run();
+
+        assert_eq!(exit, LoopExit::Quit);
+        assert_eq!(steps.get(), 10);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::shmem::Corrupted;
+use std::{fmt, io};
+
+/// Errors returned by the channel constructors and status queries.
+#[derive(Debug)]
+pub enum Error {
+    /// A system call failed. The source carries the original errno.
+    Io(io::Error),
+    Corrupted(Corrupted),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Corrupted(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Corrupted(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Corrupted> for Error {
+    fn from(e: Corrupted) -> Self {
+        Error::Corrupted(e)
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{models::*, shmem::*};
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Kind of operation recorded by `LoggedAppChannel`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelOp {
+    Push,
+    Force,
+    Receive,
+    Clear,
+}
+
+/// A single recorded operation. `accepted` is whether a push found the channel empty or a receive found a
+/// message. Forcing and clearing always succeed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelEvent {
+    pub channel: MsgChannel,
+    pub op: ChannelOp,
+    pub at: Instant,
+    pub accepted: bool,
+}
+
+/// Wrapper remembering the last operations done through it, for post-mortem debugging.
+///
+/// Only `push`, `force`, `receive` and `clear` are recorded, blocking receives like `receive_timeout` as a
+/// `Receive`. Everything else, including `pull_control`, is passed through unrecorded.
+pub struct LoggedAppChannel {
+    inner: SharedAppChannel,
+    capacity: usize,
+    events: Mutex<VecDeque<ChannelEvent>>,
+}
+
+impl LoggedAppChannel {
+    /// Keep the last `capacity` events.
+    pub fn new(inner: SharedAppChannel, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Recorded events, oldest first.
+    pub fn recent_events(&self) -> Vec<ChannelEvent> {
+        self.events.lock().unwrap().iter().copied().collect()
+    }
+
+    fn record(&self, channel: MsgChannel, op: ChannelOp, accepted: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(ChannelEvent {
+            channel,
+            op,
+            at: Instant::now(),
+            accepted,
+        });
+    }
+}
+
+impl AppChannel for LoggedAppChannel {
+    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+        self.inner.transaction(f)
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        self.inner.checked_transaction(f)
+    }
+
+    fn channel_transaction(&self, c: MsgChannel, f: &dyn Fn(&mut MSG_CHANNEL)) {
+        self.inner.channel_transaction(c, f)
+    }
+
+    fn push(&self, m: Message) -> Option<Message> {
+        let c = m.channel();
+        let rejected = self.inner.push(m);
+        self.record(c, ChannelOp::Push, rejected.is_none());
+        rejected
+    }
+
+    fn force(&self, m: Message) {
+        let c = m.channel();
+        self.inner.force(m);
+        self.record(c, ChannelOp::Force, true);
+    }
+
+    fn receive(&self, c: MsgChannel) -> Option<Vec<u8>> {
+        let v = self.inner.receive(c);
+        self.record(c, ChannelOp::Receive, v.is_some());
+        v
+    }
+
+    /// Forwarded so that a wrapped channel keeps its own blocking receive, e.g. `CondvarAppChannel`.
+    fn receive_until(&self, c: MsgChannel, deadline: Instant, poll: Duration) -> Option<Vec<u8>> {
+        let v = self.inner.receive_until(c, deadline, poll);
+        self.record(c, ChannelOp::Receive, v.is_some());
+        v
+    }
+
+    fn clear(&self, c: MsgChannel) {
+        self.inner.clear(c);
+        self.record(c, ChannelOp::Clear, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_events_recorded_in_order() {
+        let c = LoggedAppChannel::new(Arc::new(MemoryAppChannel::default()), 4);
+        let quit = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        ));
+
+        assert_eq!(c.push(quit.clone()), None);
+        assert_eq!(c.push(quit.clone()), Some(quit.clone()));
+        assert!(c.receive(MsgChannel::ProcessControlReply).is_some());
+        assert!(c.receive(MsgChannel::ProcessControlReply).is_none());
+        c.force(quit);
+        c.clear(MsgChannel::Heartbeat);
+
+        let events = c.recent_events();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| (e.channel, e.op, e.accepted))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (MsgChannel::ProcessControlReply, ChannelOp::Receive, true),
+                (MsgChannel::ProcessControlReply, ChannelOp::Receive, false),
+                (MsgChannel::ProcessControlReply, ChannelOp::Force, true),
+                (MsgChannel::Heartbeat, ChannelOp::Clear, true),
+            ]
+        );
+        assert!(events.windows(2).all(|w| w[0].at <= w[1].at));
+    }
+
+    #[test]
+    fn test_blocking_receive_forwarded() {
+        let inner = Arc::new(CondvarAppChannel::default());
+        let c = LoggedAppChannel::new(inner.clone(), 4);
+        let quit = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        ));
+
+        let received = thread::scope(|s| {
+            let receiver = s.spawn(|| {
+                // With polling this would only look again after an hour.
+                c.receive_timeout(
+                    MsgChannel::ProcessControlReply,
+                    Duration::from_secs(10),
+                    Duration::from_secs(3600),
+                )
+            });
+            thread::sleep(Duration::from_millis(20));
+            inner.force(quit.clone());
+            receiver.join().unwrap()
+        });
+        assert_eq!(received, Some(b"<quit/>".to_vec()));
+        assert_eq!(
+            c.receive_timeout(
+                MsgChannel::ProcessControlReply,
+                Duration::from_millis(10),
+                Duration::from_millis(1)
+            ),
+            None
+        );
+
+        let summary: Vec<_> = c
+            .recent_events()
+            .iter()
+            .map(|e| (e.channel, e.op, e.accepted))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (MsgChannel::ProcessControlReply, ChannelOp::Receive, true),
+                (MsgChannel::ProcessControlReply, ChannelOp::Receive, false),
+            ]
+        );
+    }
+}
@@ -1,14 +1,20 @@
-pub struct CriticalSectionGuard {}
+use crate::app::AppClient;
 
-impl Drop for CriticalSectionGuard {
+/// Marks a section during which the client's suspend and quit requests are deferred, e.g. a checkpoint write.
+#[must_use = "the critical section ends when the guard is dropped"]
+pub struct CriticalSection<'a> {
+    client: &'a AppClient,
+}
+
+impl<'a> Drop for CriticalSection<'a> {
     fn drop(&mut self) {
-        unsafe { boinc_end_critical_section(); }
+        self.client.leave_critical_section();
     }
 }
 
-impl CriticalSectionGuard {
-    fn new() -> Self {
-        unsafe { boinc_begin_critical_section(); }
-        Self {}
+impl<'a> CriticalSection<'a> {
+    pub(crate) fn new(client: &'a AppClient) -> Self {
+        client.enter_critical_section();
+        Self { client }
     }
-}
\ No newline at end of file
+}
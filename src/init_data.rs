@@ -0,0 +1,457 @@
+use crate::models::{escape, find_number, AppStatusData, MalformedMessage};
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use treexml::Element;
+use treexml_util::{parse_node, ElementExt};
+
+/// Name of the file the client writes into the slot directory.
+pub const INIT_DATA_FILE: &str = "init_data.xml";
+
+fn find_duration(root: &Element, name: &str) -> anyhow::Result<Option<Duration>> {
+    Ok(find_number(root, name, 0.0..=f64::MAX)?
+        .map(|v| {
+            Duration::try_from_secs_f64(v).map_err(|_| MalformedMessage {
+                field: name.into(),
+                value: v.to_string(),
+            })
+        })
+        .transpose()?)
+}
+
+fn write_value<W: Write, T: Display>(mut w: W, name: &str, v: &Option<T>) -> io::Result<()> {
+    match v {
+        Some(v) => writeln!(w, "<{0}>{1}</{0}>", name, escape(&v.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Description of the host from the `<host_info>` element of `init_data.xml`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostInfo {
+    /// Number of CPUs of the host, not only those allocated to the task.
+    pub p_ncpus: Option<u32>,
+    pub p_vendor: Option<String>,
+    pub p_model: Option<String>,
+    /// Physical memory in bytes.
+    pub m_nbytes: Option<f64>,
+    /// Swap space in bytes.
+    pub m_swap: Option<f64>,
+    pub os_name: Option<String>,
+    pub os_version: Option<String>,
+}
+
+impl HostInfo {
+    fn from_element(root: &Element) -> anyhow::Result<Self> {
+        Ok(Self {
+            p_ncpus: find_number(root, "p_ncpus", 0..=u32::MAX)?,
+            p_vendor: root.find_value0("p_vendor")?,
+            p_model: root.find_value0("p_model")?,
+            m_nbytes: find_number(root, "m_nbytes", 0.0..=f64::MAX)?,
+            m_swap: find_number(root, "m_swap", 0.0..=f64::MAX)?,
+            os_name: root.find_value0("os_name")?,
+            os_version: root.find_value0("os_version")?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "<host_info>")?;
+        write_value(&mut w, "p_ncpus", &self.p_ncpus)?;
+        write_value(&mut w, "p_vendor", &self.p_vendor)?;
+        write_value(&mut w, "p_model", &self.p_model)?;
+        write_value(&mut w, "m_nbytes", &self.m_nbytes)?;
+        write_value(&mut w, "m_swap", &self.m_swap)?;
+        write_value(&mut w, "os_name", &self.os_name)?;
+        write_value(&mut w, "os_version", &self.os_version)?;
+        writeln!(w, "</host_info>")
+    }
+}
+
+/// Task parameters passed by the client in `init_data.xml`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppInitData {
+    pub app_name: Option<String>,
+    pub wu_name: Option<String>,
+    pub result_name: Option<String>,
+    pub slot: Option<u32>,
+    /// Client data directory, containing the slot directories.
+    pub boinc_dir: Option<PathBuf>,
+    /// Directory holding the project's files.
+    pub project_dir: Option<PathBuf>,
+    /// CPU time consumed by the task in previous episodes.
+    pub wu_cpu_time: Option<f64>,
+    /// Number of CPUs allocated to the task, possibly fractional.
+    pub ncpus: Option<f64>,
+    /// Share of a GPU allocated to the task.
+    pub gpu_usage: Option<f64>,
+    /// How often the application is allowed to checkpoint.
+    pub checkpoint_period: Option<Duration>,
+    /// How often the application should report its progress.
+    pub fraction_done_update_period: Option<Duration>,
+    pub user_name: Option<String>,
+    pub team_name: Option<String>,
+    pub user_total_credit: Option<f64>,
+    /// Recent average credit of the user.
+    pub user_expavg_credit: Option<f64>,
+    pub host_total_credit: Option<f64>,
+    /// Recent average credit of the host.
+    pub host_expavg_credit: Option<f64>,
+    pub host_info: Option<HostInfo>,
+}
+
+impl AppInitData {
+    pub fn from_xml(s: &[u8]) -> anyhow::Result<Self> {
+        let root = parse_node(&String::from_utf8_lossy(s))?
+            .ok_or_else(|| format_err!("Empty document"))?;
+
+        Ok(Self {
+            app_name: root.find_value0("app_name")?,
+            wu_name: root.find_value0("wu_name")?,
+            result_name: root.find_value0("result_name")?,
+            slot: find_number(&root, "slot", 0..=u32::MAX)?,
+            boinc_dir: root.find_value0::<String>("boinc_dir")?.map(PathBuf::from),
+            project_dir: root
+                .find_value0::<String>("project_dir")?
+                .map(PathBuf::from),
+            wu_cpu_time: find_number(&root, "wu_cpu_time", 0.0..=f64::MAX)?,
+            ncpus: find_number(&root, "ncpus", 0.0..=f64::MAX)?,
+            gpu_usage: find_number(&root, "gpu_usage", 0.0..=f64::MAX)?,
+            checkpoint_period: find_duration(&root, "checkpoint_period")?,
+            fraction_done_update_period: find_duration(&root, "fraction_done_update_period")?,
+            user_name: root.find_value0("user_name")?,
+            team_name: root.find_value0("team_name")?,
+            user_total_credit: find_number(&root, "user_total_credit", 0.0..=f64::MAX)?,
+            user_expavg_credit: find_number(&root, "user_expavg_credit", 0.0..=f64::MAX)?,
+            host_total_credit: find_number(&root, "host_total_credit", 0.0..=f64::MAX)?,
+            host_expavg_credit: find_number(&root, "host_expavg_credit", 0.0..=f64::MAX)?,
+            host_info: root
+                .children
+                .iter()
+                .find(|e| e.name == "host_info")
+                .map(HostInfo::from_element)
+                .transpose()?,
+        })
+    }
+
+    /// Directory of the slot the task runs in. `None` if the client did not say, the working directory is the
+    /// slot directory then.
+    pub fn slot_dir(&self) -> Option<PathBuf> {
+        Some(
+            self.boinc_dir
+                .as_ref()?
+                .join("slots")
+                .join(self.slot?.to_string()),
+        )
+    }
+
+    /// Location of a checkpoint file, which belongs to the slot directory.
+    pub fn checkpoint_path<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        match self.slot_dir() {
+            Some(dir) => dir.join(name),
+            None => name.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Location of a file in the project directory, if it is known.
+    pub fn project_path<P: AsRef<Path>>(&self, name: P) -> Option<PathBuf> {
+        self.project_dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    /// Number of worker threads matching the CPU allocation, at least one.
+    pub fn thread_count(&self) -> usize {
+        self.ncpus.map_or(1, |v| (v.ceil() as usize).max(1))
+    }
+
+    /// Write `init_data.xml` contents, e.g. to emulate the client in tests.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "<app_init_data>")?;
+        write_value(&mut w, "app_name", &self.app_name)?;
+        write_value(&mut w, "wu_name", &self.wu_name)?;
+        write_value(&mut w, "result_name", &self.result_name)?;
+        write_value(&mut w, "slot", &self.slot)?;
+        write_value(
+            &mut w,
+            "boinc_dir",
+            &self.boinc_dir.as_deref().map(Path::display),
+        )?;
+        write_value(
+            &mut w,
+            "project_dir",
+            &self.project_dir.as_deref().map(Path::display),
+        )?;
+        write_value(&mut w, "wu_cpu_time", &self.wu_cpu_time)?;
+        write_value(&mut w, "ncpus", &self.ncpus)?;
+        write_value(&mut w, "gpu_usage", &self.gpu_usage)?;
+        write_value(
+            &mut w,
+            "checkpoint_period",
+            &self.checkpoint_period.map(|v| v.as_secs_f64()),
+        )?;
+        write_value(
+            &mut w,
+            "fraction_done_update_period",
+            &self.fraction_done_update_period.map(|v| v.as_secs_f64()),
+        )?;
+        write_value(&mut w, "user_name", &self.user_name)?;
+        write_value(&mut w, "team_name", &self.team_name)?;
+        write_value(&mut w, "user_total_credit", &self.user_total_credit)?;
+        write_value(&mut w, "user_expavg_credit", &self.user_expavg_credit)?;
+        write_value(&mut w, "host_total_credit", &self.host_total_credit)?;
+        write_value(&mut w, "host_expavg_credit", &self.host_expavg_credit)?;
+        if let Some(host_info) = &self.host_info {
+            host_info.write_to(&mut w)?;
+        }
+        writeln!(w, "</app_init_data>")
+    }
+
+    pub fn from_reader<R: Read>(mut r: R) -> anyhow::Result<Self> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        Self::from_xml(&buf)
+    }
+}
+
+/// Progress restored from the application's own checkpoint on restart.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CheckpointState {
+    pub cpu_time: f64,
+    pub fraction_done: f64,
+}
+
+impl AppStatusData {
+    /// Status to report at startup, before any work has been done in this episode.
+    pub fn initial(init: &AppInitData, checkpoint: Option<&CheckpointState>) -> Self {
+        let (cpu_time, fraction_done) = match checkpoint {
+            Some(c) => (c.cpu_time, c.fraction_done),
+            None => (init.wu_cpu_time.unwrap_or(0.0), 0.0),
+        };
+        Self {
+            current_cpu_time: cpu_time,
+            checkpoint_cpu_time: cpu_time,
+            want_network: false,
+            fraction_done,
+            other_pid: None,
+            bytes_sent: None,
+            bytes_received: None,
+            working_set_size: None,
+            elapsed_time: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_periods_parse() {
+        let fixture = "<app_init_data>
+<wu_name>wu_1</wu_name>
+<slot>3</slot>
+<checkpoint_period>60.0</checkpoint_period>
+<fraction_done_update_period>0.5</fraction_done_update_period>
+</app_init_data>"
+            .as_bytes();
+
+        let result = AppInitData::from_reader(fixture).unwrap();
+
+        assert_eq!(result.wu_name, Some("wu_1".into()));
+        assert_eq!(result.slot, Some(3));
+        assert_eq!(result.checkpoint_period, Some(Duration::from_secs(60)));
+        assert_eq!(
+            result.fraction_done_update_period,
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn test_resource_allocation_parse() {
+        let multi_cpu =
+            AppInitData::from_xml(b"<app_init_data><ncpus>4</ncpus></app_init_data>").unwrap();
+        assert_eq!(multi_cpu.ncpus, Some(4.0));
+        assert_eq!(multi_cpu.gpu_usage, None);
+        assert_eq!(multi_cpu.thread_count(), 4);
+
+        let gpu_share = AppInitData::from_xml(
+            b"<app_init_data><ncpus>0.5</ncpus><gpu_usage>0.25</gpu_usage></app_init_data>",
+        )
+        .unwrap();
+        assert_eq!(gpu_share.ncpus, Some(0.5));
+        assert_eq!(gpu_share.gpu_usage, Some(0.25));
+        assert_eq!(gpu_share.thread_count(), 1);
+
+        assert_eq!(AppInitData::default().thread_count(), 1);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let expectation = AppInitData {
+            app_name: Some("uppercase".into()),
+            wu_name: Some("wu_<1>&2".into()),
+            result_name: Some("wu_1_0".into()),
+            slot: Some(7),
+            boinc_dir: Some("/var/lib/boinc".into()),
+            project_dir: Some("/var/lib/boinc/projects/example.org".into()),
+            wu_cpu_time: Some(12.5),
+            ncpus: Some(4.0),
+            gpu_usage: Some(0.5),
+            checkpoint_period: Some(Duration::from_secs(300)),
+            fraction_done_update_period: Some(Duration::from_millis(250)),
+            user_name: Some("alice".into()),
+            team_name: None,
+            user_total_credit: Some(1234.5),
+            user_expavg_credit: Some(10.25),
+            host_total_credit: Some(100.0),
+            host_expavg_credit: None,
+            host_info: Some(HostInfo {
+                p_ncpus: Some(8),
+                p_model: Some("Example CPU @ 3.00GHz".into()),
+                m_nbytes: Some(17179869184.0),
+                ..Default::default()
+            }),
+        };
+
+        let mut buf = Vec::new();
+        expectation.write_to(&mut buf).unwrap();
+
+        assert_eq!(AppInitData::from_xml(&buf).unwrap(), expectation);
+    }
+
+    #[test]
+    fn test_user_and_host_info_parse() {
+        let fixture = b"<app_init_data>
+<major_version>7</major_version>
+<app_name>uppercase</app_name>
+<user_name>alice</user_name>
+<team_name>Team &amp; Co</team_name>
+<user_total_credit>123456.789</user_total_credit>
+<user_expavg_credit>42.5</user_expavg_credit>
+<host_total_credit>9876.5</host_total_credit>
+<host_expavg_credit>3.25</host_expavg_credit>
+<host_info>
+    <timezone>7200</timezone>
+    <domain_name>example-host</domain_name>
+    <p_ncpus>16</p_ncpus>
+    <p_vendor>GenuineIntel</p_vendor>
+    <p_model>Example CPU @ 3.00GHz [Family 6 Model 158]</p_model>
+    <p_fpops>4.5e9</p_fpops>
+    <m_nbytes>33554432000.000000</m_nbytes>
+    <m_swap>2147483648.000000</m_swap>
+    <d_total>500000000000.000000</d_total>
+    <os_name>Linux Ubuntu</os_name>
+    <os_version>Ubuntu 22.04 LTS [6.5.0-generic]</os_version>
+</host_info>
+<ncpus>4</ncpus>
+</app_init_data>";
+
+        let init = AppInitData::from_xml(fixture).unwrap();
+        assert_eq!(init.app_name, Some("uppercase".into()));
+        assert_eq!(init.user_name, Some("alice".into()));
+        assert_eq!(init.team_name, Some("Team & Co".into()));
+        assert_eq!(init.user_total_credit, Some(123456.789));
+        assert_eq!(init.user_expavg_credit, Some(42.5));
+        assert_eq!(init.host_total_credit, Some(9876.5));
+        assert_eq!(init.host_expavg_credit, Some(3.25));
+        assert_eq!(init.ncpus, Some(4.0));
+        assert_eq!(
+            init.host_info,
+            Some(HostInfo {
+                p_ncpus: Some(16),
+                p_vendor: Some("GenuineIntel".into()),
+                p_model: Some("Example CPU @ 3.00GHz [Family 6 Model 158]".into()),
+                m_nbytes: Some(33554432000.0),
+                m_swap: Some(2147483648.0),
+                os_name: Some("Linux Ubuntu".into()),
+                os_version: Some("Ubuntu 22.04 LTS [6.5.0-generic]".into()),
+            })
+        );
+
+        assert_eq!(AppInitData::default().host_info, None);
+    }
+
+    #[test]
+    fn test_path_composition() {
+        let init = AppInitData::from_xml(
+            b"<app_init_data>
+<slot>2</slot>
+<boinc_dir>/var/lib/boinc</boinc_dir>
+<project_dir>/var/lib/boinc/projects/example.org</project_dir>
+</app_init_data>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            init.checkpoint_path("state.chk"),
+            PathBuf::from("/var/lib/boinc/slots/2/state.chk")
+        );
+        assert_eq!(
+            init.project_path("input.dat"),
+            Some(PathBuf::from(
+                "/var/lib/boinc/projects/example.org/input.dat"
+            ))
+        );
+
+        let standalone = AppInitData::default();
+        assert_eq!(
+            standalone.checkpoint_path("state.chk"),
+            PathBuf::from("state.chk")
+        );
+        assert_eq!(standalone.project_path("input.dat"), None);
+    }
+
+    #[test]
+    fn test_negative_period_rejected() {
+        let fixture = b"<app_init_data><checkpoint_period>-1</checkpoint_period></app_init_data>";
+
+        assert!(AppInitData::from_xml(fixture).is_err());
+    }
+
+    #[test]
+    fn test_numeric_fields_rejected() {
+        for (field, v) in [
+            ("slot", "-1"),
+            ("slot", "4294967296"),
+            ("wu_cpu_time", "long"),
+            ("ncpus", "many"),
+            ("ncpus", "1e999"),
+            ("gpu_usage", "NaN"),
+            ("fraction_done_update_period", "1e999"),
+        ] {
+            let xml = format!("<app_init_data><{0}>{1}</{0}></app_init_data>", field, v);
+            let e = AppInitData::from_xml(xml.as_bytes()).unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<MalformedMessage>()
+                    .map(|e| e.field.as_str()),
+                Some(field),
+                "{}",
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn test_initial_status_after_restart() {
+        let init = AppInitData::from_xml(
+            b"<app_init_data><wu_cpu_time>12.5</wu_cpu_time></app_init_data>",
+        )
+        .unwrap();
+
+        let fresh = AppStatusData::initial(&init, None);
+        assert_eq!(fresh.current_cpu_time, 12.5);
+        assert_eq!(fresh.fraction_done, 0.0);
+
+        let checkpoint = CheckpointState {
+            cpu_time: 30.0,
+            fraction_done: 0.25,
+        };
+        let restarted = AppStatusData::initial(&init, Some(&checkpoint));
+        assert_eq!(restarted.current_cpu_time, 30.0);
+        assert_eq!(restarted.checkpoint_cpu_time, 30.0);
+        assert_eq!(restarted.fraction_done, 0.25);
+    }
+}
@@ -5,16 +5,30 @@
 
 #![allow(clippy::mutex_atomic)]
 
+pub mod app;
+#[cfg(feature = "benchmark")]
+pub mod benchmark;
+pub mod changes;
 pub mod connection;
 pub mod connection_util;
+pub mod control_loop;
+pub mod error;
+pub mod event_log;
+pub mod guards;
+pub mod init_data;
 pub mod models;
+pub mod pool;
+pub mod role;
 pub mod shmem;
+pub mod throttle;
+pub mod trickle;
+pub mod usage;
 
 #[cfg(test)]
 mod tests {
     use crate::{connection::*, models::*, shmem::*};
     use futures::prelude::*;
-    use std::{sync::Arc, time::Duration};
+    use std::{env, process::Command, sync::Arc, time::Duration};
 
     #[test]
     fn test_from_ipc() {
@@ -26,6 +40,8 @@ mod tests {
             other_pid: Some(345),
 
             bytes_received: None,
+            working_set_size: None,
+            elapsed_time: None,
             bytes_sent: None,
         });
 
@@ -56,6 +72,8 @@ mod tests {
             other_pid: None,
             bytes_sent: Some(256.0),
             bytes_received: Some(128.0),
+            working_set_size: None,
+            elapsed_time: None,
         };
         let expectation = Some(StatusMessage::AppStatus(fixture.clone()));
 
@@ -89,6 +107,8 @@ mod tests {
             other_pid: None,
             bytes_sent: Some(256.0),
             bytes_received: Some(128.0),
+            working_set_size: None,
+            elapsed_time: None,
         };
         let expectation = Some(StatusMessage::AppStatus(fixture.clone()));
 
@@ -114,4 +134,57 @@ mod tests {
 
         assert_eq!(expectation, result);
     }
+
+    /// Set in the child process spawned by `test_cross_process_mmap` to the path of the shared file.
+    const CHILD_MMAP_ENV: &str = "BOINC_APP_API_TEST_CHILD_MMAP";
+    const CROSS_PROCESS_MESSAGES: usize = 50;
+
+    fn heartbeat(i: usize) -> ControlMessage {
+        ControlMessage::Heartbeat(Heartbeat {
+            wss: Some(i as f64),
+            max_wss: Some(1e9),
+        })
+    }
+
+    #[test]
+    /// In this test a child process writes control messages into a mmapped file which the parent reads back.
+    fn test_cross_process_mmap() {
+        if let Some(path) = env::var_os(CHILD_MMAP_ENV) {
+            let c = MmapAppChannel::new(path).unwrap();
+            for i in 0..CROSS_PROCESS_MESSAGES {
+                let mut m = Message::Control(heartbeat(i));
+                while let Some(rejected) = c.push(m) {
+                    m = rejected;
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            return;
+        }
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mmap_path = tmp.path().join(MMAP_FILE_NAME);
+        let c = MmapAppChannel::new(&mmap_path).unwrap();
+
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(["tests::test_cross_process_mmap", "--exact", "--nocapture"])
+            .env(CHILD_MMAP_ENV, &mmap_path)
+            .spawn()
+            .unwrap();
+
+        for i in 0..CROSS_PROCESS_MESSAGES {
+            let v = c
+                .receive_timeout(
+                    MsgChannel::Heartbeat,
+                    Duration::from_secs(10),
+                    Duration::from_millis(1),
+                )
+                .unwrap_or_else(|| panic!("message {} did not arrive", i));
+            assert_eq!(
+                ControlMessage::from_raw(ControlMsgChannel::Heartbeat, v).unwrap(),
+                heartbeat(i)
+            );
+        }
+
+        assert!(child.wait().unwrap().success());
+    }
 }
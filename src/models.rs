@@ -1,10 +1,61 @@
 use anyhow::format_err;
+use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt, ops::RangeInclusive, str::FromStr};
 use treexml::Element;
 use treexml_util::{parse_node, ElementExt};
 
 fn parse_xml_data(s: &[u8]) -> anyhow::Result<Element> {
-    Ok(parse_node(&format!("<root>{}</root>", &String::from_utf8_lossy(s)))?.unwrap())
+    parse_node(&format!("<root>{}</root>", &String::from_utf8_lossy(s)))?
+        .ok_or_else(|| format_err!("Empty document"))
+}
+
+/// A numeric field is not a number or outside of its valid range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MalformedMessage {
+    pub field: String,
+    pub value: String,
+}
+
+impl fmt::Display for MalformedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "malformed value for {}: {:?}", self.field, self.value)
+    }
+}
+
+impl std::error::Error for MalformedMessage {}
+
+fn parse_number<T: FromStr + PartialOrd>(
+    name: &str,
+    text: &str,
+    range: RangeInclusive<T>,
+) -> Result<T, MalformedMessage> {
+    match text.trim().parse::<T>() {
+        Ok(v) if range.contains(&v) => Ok(v),
+        _ => Err(MalformedMessage {
+            field: name.into(),
+            value: text.into(),
+        }),
+    }
+}
+
+/// Escape text for inclusion in element contents.
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Numeric child element, rejecting values that do not parse or fall outside of `range`. NaN is never in range.
+pub(crate) fn find_number<T: FromStr + PartialOrd>(
+    root: &Element,
+    name: &str,
+    range: RangeInclusive<T>,
+) -> anyhow::Result<Option<T>> {
+    Ok(root
+        .find_value0::<String>(name)?
+        .map(|text| parse_number(name, &text, range))
+        .transpose()?)
 }
 
 pub(crate) trait MsgChannelXml
@@ -13,6 +64,22 @@ where
 {
     fn from_xml(s: &[u8]) -> anyhow::Result<Self>;
     fn to_xml(&self) -> Vec<u8>;
+
+    /// Length of `to_xml` output in bytes.
+    fn serialized_len(&self) -> usize {
+        self.to_xml().len()
+    }
+}
+
+/// Sink that only counts the bytes written to it.
+#[derive(Default)]
+struct ByteCounter(usize);
+
+impl fmt::Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -77,6 +144,23 @@ pub enum MsgChannel {
     TrickleDown,
 }
 
+impl MsgChannel {
+    pub fn enum_iter() -> impl Iterator<Item = Self> {
+        [
+            Self::ProcessControlRequest,
+            Self::ProcessControlReply,
+            Self::GraphicsRequest,
+            Self::GraphicsReply,
+            Self::Heartbeat,
+            Self::AppStatus,
+            Self::TrickleUp,
+            Self::TrickleDown,
+        ]
+        .iter()
+        .copied()
+    }
+}
+
 impl From<ControlMsgChannel> for MsgChannel {
     fn from(m: ControlMsgChannel) -> MsgChannel {
         match m {
@@ -99,12 +183,112 @@ impl From<StatusMsgChannel> for MsgChannel {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+/// Fails with the original channel if it carries status messages.
+impl TryFrom<MsgChannel> for ControlMsgChannel {
+    type Error = MsgChannel;
+
+    fn try_from(m: MsgChannel) -> Result<Self, MsgChannel> {
+        match m {
+            MsgChannel::ProcessControlRequest => Ok(ControlMsgChannel::ProcessControlRequest),
+            MsgChannel::GraphicsRequest => Ok(ControlMsgChannel::GraphicsRequest),
+            MsgChannel::Heartbeat => Ok(ControlMsgChannel::Heartbeat),
+            MsgChannel::TrickleDown => Ok(ControlMsgChannel::TrickleDown),
+            other => Err(other),
+        }
+    }
+}
+
+/// Fails with the original channel if it carries control messages.
+impl TryFrom<MsgChannel> for StatusMsgChannel {
+    type Error = MsgChannel;
+
+    fn try_from(m: MsgChannel) -> Result<Self, MsgChannel> {
+        match m {
+            MsgChannel::ProcessControlReply => Ok(StatusMsgChannel::ProcessControlReply),
+            MsgChannel::GraphicsReply => Ok(StatusMsgChannel::GraphicsReply),
+            MsgChannel::AppStatus => Ok(StatusMsgChannel::AppStatus),
+            MsgChannel::TrickleUp => Ok(StatusMsgChannel::TrickleUp),
+            other => Err(other),
+        }
+    }
+}
+
+bitflags! {
+    /// Why the client suspended computation, as the `SUSPEND_REASON_*` values in BOINC's `common_defs.h`. Empty if
+    /// the client did not say.
+    ///
+    /// Values up to `OS` are single bits and may be combined. The values after it (4097-4100) are plain numbers,
+    /// so they are kept in bits above the 32-bit wire range to tell them apart from combinations like
+    /// `BATTERIES | OS`. Use `from_raw` and `to_raw` to convert from and to the wire value.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct SuspendReason: u64 {
+        const BATTERIES = 1;
+        const USER_ACTIVE = 2;
+        const USER_REQ = 4;
+        const TIME_OF_DAY = 8;
+        const BENCHMARKS = 16;
+        const DISK_SIZE = 32;
+        const CPU_THROTTLE = 64;
+        const NO_RECENT_INPUT = 128;
+        const INITIAL_DELAY = 256;
+        const EXCLUSIVE_APP_RUNNING = 512;
+        const CPU_USAGE = 1024;
+        const NETWORK_QUOTA_EXCEEDED = 2048;
+        const OS = 4096;
+        /// `SUSPEND_REASON_WIFI_STATE` (4097).
+        const WIFI_STATE = 1 << 32;
+        /// `SUSPEND_REASON_BATTERY_CHARGING` (4098).
+        const BATTERY_CHARGING = 1 << 33;
+        /// `SUSPEND_REASON_BATTERY_OVERHEATED` (4099).
+        const BATTERY_OVERHEATED = 1 << 34;
+        /// `SUSPEND_REASON_NO_GUI_KEEPALIVE` (4100).
+        const NO_GUI_KEEPALIVE = 1 << 35;
+    }
+}
+
+impl SuspendReason {
+    const PLAIN_VALUES: [(u32, SuspendReason); 4] = [
+        (4097, SuspendReason::WIFI_STATE),
+        (4098, SuspendReason::BATTERY_CHARGING),
+        (4099, SuspendReason::BATTERY_OVERHEATED),
+        (4100, SuspendReason::NO_GUI_KEEPALIVE),
+    ];
+
+    /// Decode a `<suspend_reason>` value. Bits without a name are kept.
+    pub fn from_raw(v: u32) -> Self {
+        Self::PLAIN_VALUES
+            .iter()
+            .find(|(raw, _)| *raw == v)
+            .map(|(_, reason)| *reason)
+            .unwrap_or_else(|| Self::from_bits_retain(u64::from(v)))
+    }
+
+    /// Value sent on the wire. A plain value combined with anything else cannot be represented and only the bits
+    /// are kept.
+    pub fn to_raw(self) -> u32 {
+        Self::PLAIN_VALUES
+            .iter()
+            .find(|(_, reason)| *reason == self)
+            .map(|(raw, _)| *raw)
+            .unwrap_or(self.bits() as u32)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ProcessControlRequest {
     Quit,
-    Suspend,
+    /// Suspend computation. The client may say why in a `<suspend_reason>` value.
+    Suspend {
+        reason: SuspendReason,
+    },
     Resume,
-    Abort,
+    /// Stop network activity while computation continues.
+    SuspendNetwork,
+    ResumeNetwork,
+    /// Abort the task, optionally with the reason supplied by the client.
+    Abort {
+        reason: Option<String>,
+    },
 }
 
 impl MsgChannelXml for ProcessControlRequest {
@@ -116,30 +300,106 @@ impl MsgChannelXml for ProcessControlRequest {
         let variant = root
             .children
             .pop()
-            .ok_or_else(|| format_err!("No variant detected"))?
-            .name;
+            .ok_or_else(|| format_err!("No variant detected"))?;
 
-        Ok(match variant.as_ref() {
+        Ok(match variant.name.as_ref() {
+            "quit" => Quit,
+            "suspend" => Suspend {
+                reason: SuspendReason::from_raw(
+                    find_number(&variant, "suspend_reason", 0..=u32::MAX)?.unwrap_or_default(),
+                ),
+            },
+            "resume" => Resume,
+            "suspend_network" => SuspendNetwork,
+            "resume_network" => ResumeNetwork,
+            "abort" => Abort {
+                reason: variant.text,
+            },
+            _ => {
+                return Err(format_err!("Invalid variant detected: {}", &variant.name));
+            }
+        })
+    }
+
+    fn to_xml(&self) -> Vec<u8> {
+        use self::ProcessControlRequest::*;
+
+        match self {
+            Quit => "<quit/>".into(),
+            Suspend { reason } if reason.is_empty() => "<suspend/>".into(),
+            Suspend { reason } => format!(
+                "<suspend><suspend_reason>{}</suspend_reason></suspend>",
+                reason.to_raw()
+            )
+            .into(),
+            Resume => "<resume/>".into(),
+            SuspendNetwork => "<suspend_network/>".into(),
+            ResumeNetwork => "<resume_network/>".into(),
+            Abort { reason: None } => "<abort/>".into(),
+            Abort { reason: Some(v) } => format!("<abort>{}</abort>", escape(v)).into(),
+        }
+    }
+}
+
+/// Acknowledgement that the application handled a `ProcessControlRequest`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProcessControlReply {
+    Quit,
+    Suspend,
+    Resume,
+    SuspendNetwork,
+    ResumeNetwork,
+    Abort,
+}
+
+impl ProcessControlReply {
+    pub fn acknowledge(request: &ProcessControlRequest) -> Self {
+        match request {
+            ProcessControlRequest::Quit => ProcessControlReply::Quit,
+            ProcessControlRequest::Suspend { .. } => ProcessControlReply::Suspend,
+            ProcessControlRequest::Resume => ProcessControlReply::Resume,
+            ProcessControlRequest::SuspendNetwork => ProcessControlReply::SuspendNetwork,
+            ProcessControlRequest::ResumeNetwork => ProcessControlReply::ResumeNetwork,
+            ProcessControlRequest::Abort { .. } => ProcessControlReply::Abort,
+        }
+    }
+}
+
+impl MsgChannelXml for ProcessControlReply {
+    fn from_xml(s: &[u8]) -> anyhow::Result<Self> {
+        use self::ProcessControlReply::*;
+
+        let mut root = parse_xml_data(s)?;
+
+        let variant = root
+            .children
+            .pop()
+            .ok_or_else(|| format_err!("No variant detected"))?;
+
+        Ok(match variant.name.as_ref() {
             "quit" => Quit,
             "suspend" => Suspend,
             "resume" => Resume,
+            "suspend_network" => SuspendNetwork,
+            "resume_network" => ResumeNetwork,
             "abort" => Abort,
             _ => {
-                return Err(format_err!("Invalid variant detected: {}", &variant));
+                return Err(format_err!("Invalid variant detected: {}", &variant.name));
             }
         })
     }
 
     fn to_xml(&self) -> Vec<u8> {
-        use self::ProcessControlRequest::*;
+        use self::ProcessControlReply::*;
 
-        match *self {
-            Quit => "<quit/>",
-            Suspend => "<suspend/>",
-            Resume => "<resume/>",
-            Abort => "<abort/>",
+        match self {
+            Quit => "<quit/>".into(),
+            Suspend => "<suspend/>".into(),
+            Resume => "<resume/>".into(),
+            SuspendNetwork => "<suspend_network/>".into(),
+            ResumeNetwork => "<resume_network/>".into(),
+            Abort => "<abort/>".into(),
         }
-        .into()
     }
 }
 
@@ -171,9 +431,13 @@ impl MsgChannelXml for GraphicsReplyData {
     }
 }
 
+/// Periodic message from the client. Besides proving the client is alive it carries memory figures; the client
+/// sends no timing values in it.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Heartbeat {
+    /// Working set size the client measured for the application, in bytes.
     pub wss: Option<f64>,
+    /// Largest working set size the application is allowed to use, in bytes.
     pub max_wss: Option<f64>,
 }
 
@@ -182,8 +446,8 @@ impl MsgChannelXml for Heartbeat {
         let root = parse_xml_data(s)?;
 
         Ok(Self {
-            wss: root.find_value0("wss")?,
-            max_wss: root.find_value0("max_wss")?,
+            wss: find_number(&root, "wss", 0.0..=f64::MAX)?,
+            max_wss: find_number(&root, "max_wss", 0.0..=f64::MAX)?,
         })
     }
 
@@ -208,6 +472,37 @@ pub struct AppStatusData {
     pub other_pid: Option<i64>,
     pub bytes_sent: Option<f64>,
     pub bytes_received: Option<f64>,
+    /// Resident memory of the application, in bytes.
+    pub working_set_size: Option<f64>,
+    /// Wall-clock seconds since the application started, which exceeds CPU time while waiting on I/O or a GPU.
+    pub elapsed_time: Option<f64>,
+}
+
+impl AppStatusData {
+    /// Add network traffic done by the application itself to the reported counters.
+    pub fn add_traffic(&mut self, sent: f64, received: f64) {
+        *self.bytes_sent.get_or_insert(0.0) += sent;
+        *self.bytes_received.get_or_insert(0.0) += received;
+    }
+
+    /// Like `==`, but numbers may differ by up to `tol`, e.g. to tell whether a new report is worth sending.
+    pub fn approx_eq(&self, other: &Self, tol: f64) -> bool {
+        let close = |a: f64, b: f64| (a - b).abs() <= tol;
+        let close_opt = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => close(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+
+        close(self.current_cpu_time, other.current_cpu_time)
+            && close(self.checkpoint_cpu_time, other.checkpoint_cpu_time)
+            && self.want_network == other.want_network
+            && close(self.fraction_done, other.fraction_done)
+            && self.other_pid == other.other_pid
+            && close_opt(self.bytes_sent, other.bytes_sent)
+            && close_opt(self.bytes_received, other.bytes_received)
+            && close_opt(self.working_set_size, other.working_set_size)
+            && close_opt(self.elapsed_time, other.elapsed_time)
+    }
 }
 
 impl MsgChannelXml for AppStatusData {
@@ -215,40 +510,67 @@ impl MsgChannelXml for AppStatusData {
         let root = parse_xml_data(s)?;
 
         Ok(Self {
-            current_cpu_time: root.find_value1("current_cpu_time")?,
-            checkpoint_cpu_time: root.find_value1("checkpoint_cpu_time")?,
+            // The client accepts progress-only reports without CPU times.
+            current_cpu_time: find_number(&root, "current_cpu_time", 0.0..=f64::MAX)?
+                .unwrap_or(0.0),
+            checkpoint_cpu_time: find_number(&root, "checkpoint_cpu_time", 0.0..=f64::MAX)?
+                .unwrap_or(0.0),
             want_network: root.find_bool("want_network")?,
-            fraction_done: root.find_value1("fraction_done")?,
-            other_pid: root.find_value0("other_pid")?,
-            bytes_sent: root.find_value0("bytes_sent")?,
-            bytes_received: root.find_value0("bytes_received")?,
+            fraction_done: find_number(&root, "fraction_done", 0.0..=1.0)?
+                .ok_or_else(|| format_err!("Missing fraction_done"))?,
+            other_pid: find_number(&root, "other_pid", 1..=i64::MAX)?,
+            bytes_sent: find_number(&root, "bytes_sent", 0.0..=f64::MAX)?,
+            bytes_received: find_number(&root, "bytes_received", 0.0..=f64::MAX)?,
+            working_set_size: find_number(&root, "working_set_size", 0.0..=f64::MAX)?,
+            elapsed_time: find_number(&root, "elapsed_time", 0.0..=f64::MAX)?,
         })
     }
 
     fn to_xml(&self) -> Vec<u8> {
         let mut s = String::new();
-        s += &format!(
-            "<current_cpu_time>{}</current_cpu_time>\n",
+        self.write_xml(&mut s).unwrap();
+        s.into()
+    }
+
+    fn serialized_len(&self) -> usize {
+        let mut counter = ByteCounter::default();
+        self.write_xml(&mut counter).unwrap();
+        counter.0
+    }
+}
+
+impl AppStatusData {
+    fn write_xml<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        writeln!(
+            w,
+            "<current_cpu_time>{}</current_cpu_time>",
             self.current_cpu_time
-        );
-        s += &format!(
-            "<checkpoint_cpu_time>{}</checkpoint_cpu_time>\n",
+        )?;
+        writeln!(
+            w,
+            "<checkpoint_cpu_time>{}</checkpoint_cpu_time>",
             self.checkpoint_cpu_time
-        );
+        )?;
         if self.want_network {
-            s += "<want_network>1</want_network>\n";
+            writeln!(w, "<want_network>1</want_network>")?;
         }
-        s += &format!("<fraction_done>{}</fraction_done>\n", self.fraction_done);
+        writeln!(w, "<fraction_done>{}</fraction_done>", self.fraction_done)?;
         if let Some(v) = self.other_pid {
-            s += &format!("<other_pid>{}</other_pid>\n", v);
+            writeln!(w, "<other_pid>{}</other_pid>", v)?;
         }
         if let Some(v) = self.bytes_sent {
-            s += &format!("<bytes_sent>{}</bytes_sent>\n", v);
+            writeln!(w, "<bytes_sent>{}</bytes_sent>", v)?;
         }
         if let Some(v) = self.bytes_received {
-            s += &format!("<bytes_received>{}</bytes_received>\n", v);
+            writeln!(w, "<bytes_received>{}</bytes_received>", v)?;
         }
-        s.into()
+        if let Some(v) = self.working_set_size {
+            writeln!(w, "<working_set_size>{}</working_set_size>", v)?;
+        }
+        if let Some(v) = self.elapsed_time {
+            writeln!(w, "<elapsed_time>{}</elapsed_time>", v)?;
+        }
+        Ok(())
     }
 }
 
@@ -322,6 +644,9 @@ pub enum ControlMessage {
     Heartbeat(Heartbeat),
     #[serde(rename = "trickle_down")]
     TrickleDown(TrickleDownData),
+    /// Process control request with a tag this crate does not know, passed through as is.
+    #[serde(rename = "unknown")]
+    Unknown(Vec<u8>),
 }
 
 impl ControlMessage {
@@ -331,6 +656,28 @@ impl ControlMessage {
             ControlMessage::GraphicsRequest => ControlMsgChannel::GraphicsRequest,
             ControlMessage::Heartbeat(_) => ControlMsgChannel::Heartbeat,
             ControlMessage::TrickleDown(_) => ControlMsgChannel::TrickleDown,
+            ControlMessage::Unknown(_) => ControlMsgChannel::ProcessControlRequest,
+        }
+    }
+
+    /// Whether the application should stop its current work as soon as possible: quit and abort requests.
+    pub fn is_urgent(&self) -> bool {
+        matches!(
+            self,
+            ControlMessage::ProcessControlRequest(
+                ProcessControlRequest::Quit | ProcessControlRequest::Abort { .. }
+            )
+        )
+    }
+
+    /// Payload length in bytes once serialized, to be checked against `MSG_CHANNEL::MAX_PAYLOAD`.
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            ControlMessage::ProcessControlRequest(v) => v.serialized_len(),
+            ControlMessage::GraphicsRequest => 0,
+            ControlMessage::Heartbeat(v) => v.serialized_len(),
+            ControlMessage::TrickleDown(v) => v.serialized_len(),
+            ControlMessage::Unknown(v) => v.len(),
         }
     }
 
@@ -364,6 +711,7 @@ impl From<ControlMessage> for (ControlMsgChannel, Vec<u8>) {
             ControlMessage::TrickleDown(v) => {
                 (ControlMsgChannel::TrickleDown, MsgChannelXml::to_xml(&v))
             }
+            ControlMessage::Unknown(v) => (ControlMsgChannel::ProcessControlRequest, v),
         }
     }
 }
@@ -373,7 +721,7 @@ impl From<ControlMessage> for (ControlMsgChannel, Vec<u8>) {
 /// Message from app to control
 pub enum StatusMessage {
     #[serde(rename = "process_control_reply")]
-    ProcessControlReply,
+    ProcessControlReply(ProcessControlReply),
     #[serde(rename = "graphics_reply")]
     GraphicsReply(GraphicsReplyData),
     #[serde(rename = "app_status")]
@@ -383,9 +731,30 @@ pub enum StatusMessage {
 }
 
 impl StatusMessage {
+    pub fn channel(&self) -> StatusMsgChannel {
+        match self {
+            StatusMessage::ProcessControlReply(_) => StatusMsgChannel::ProcessControlReply,
+            StatusMessage::GraphicsReply(_) => StatusMsgChannel::GraphicsReply,
+            StatusMessage::AppStatus(_) => StatusMsgChannel::AppStatus,
+            StatusMessage::TrickleUp(_) => StatusMsgChannel::TrickleUp,
+        }
+    }
+
+    /// Payload length in bytes once serialized, to be checked against `MSG_CHANNEL::MAX_PAYLOAD`.
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            StatusMessage::ProcessControlReply(v) => v.serialized_len(),
+            StatusMessage::GraphicsReply(v) => v.serialized_len(),
+            StatusMessage::AppStatus(v) => v.serialized_len(),
+            StatusMessage::TrickleUp(v) => v.serialized_len(),
+        }
+    }
+
     pub fn from_raw(c: StatusMsgChannel, b: Vec<u8>) -> anyhow::Result<Self> {
         match c {
-            StatusMsgChannel::ProcessControlReply => Ok(StatusMessage::ProcessControlReply),
+            StatusMsgChannel::ProcessControlReply => {
+                MsgChannelXml::from_xml(&b).map(StatusMessage::ProcessControlReply)
+            }
             StatusMsgChannel::GraphicsReply => {
                 MsgChannelXml::from_xml(&b).map(StatusMessage::GraphicsReply)
             }
@@ -402,8 +771,8 @@ impl StatusMessage {
 impl From<StatusMessage> for (StatusMsgChannel, Vec<u8>) {
     fn from(v: StatusMessage) -> (StatusMsgChannel, Vec<u8>) {
         match v {
-            StatusMessage::ProcessControlReply => {
-                (StatusMsgChannel::ProcessControlReply, "".into())
+            StatusMessage::ProcessControlReply(ref v) => {
+                (StatusMsgChannel::ProcessControlReply, v.to_xml())
             }
             StatusMessage::GraphicsReply(ref v) => (StatusMsgChannel::GraphicsReply, v.to_xml()),
             StatusMessage::AppStatus(ref v) => (StatusMsgChannel::AppStatus, v.to_xml()),
@@ -419,6 +788,31 @@ pub enum Message {
     Status(StatusMessage),
 }
 
+impl Message {
+    pub fn channel(&self) -> MsgChannel {
+        match self {
+            Message::Control(m) => m.channel().into(),
+            Message::Status(m) => m.channel().into(),
+        }
+    }
+
+    /// Payload length in bytes once serialized, to be checked against `MSG_CHANNEL::MAX_PAYLOAD`.
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            Message::Control(m) => m.serialized_len(),
+            Message::Status(m) => m.serialized_len(),
+        }
+    }
+
+    pub fn from_raw(c: MsgChannel, b: Vec<u8>) -> anyhow::Result<Self> {
+        match ControlMsgChannel::try_from(c) {
+            Ok(c) => ControlMessage::from_raw(c, b).map(Message::Control),
+            Err(c) => StatusMessage::from_raw(StatusMsgChannel::try_from(c).unwrap(), b)
+                .map(Message::Status),
+        }
+    }
+}
+
 impl From<Message> for (MsgChannel, Vec<u8>) {
     fn from(v: Message) -> (MsgChannel, Vec<u8>) {
         match v {
@@ -437,6 +831,61 @@ impl From<Message> for (MsgChannel, Vec<u8>) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    fn xml_fragments() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(
+            prop::sample::select(vec![
+                "<",
+                ">",
+                "/",
+                "</root>",
+                "<root>",
+                "<quit/>",
+                "<abort>",
+                "</abort>",
+                "<wss>",
+                "</wss>",
+                "<fraction_done>",
+                "</fraction_done>",
+                "<app_init_data>",
+                "</app_init_data>",
+                "<checkpoint_period>",
+                "</checkpoint_period>",
+                "1e999",
+                "-1",
+                "NaN",
+                "&amp;",
+                "&",
+                "\u{0}",
+                "\u{fffd}",
+            ]),
+            0..16,
+        )
+        .prop_map(|v| v.concat().into_bytes())
+    }
+
+    fn parse_all(payload: &[u8]) {
+        for c in ControlMsgChannel::enum_iter() {
+            let _ = ControlMessage::from_raw(c, payload.to_vec());
+        }
+        for c in StatusMsgChannel::enum_iter() {
+            let _ = StatusMessage::from_raw(c, payload.to_vec());
+        }
+        let _ = crate::init_data::AppInitData::from_reader(payload);
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_arbitrary_bytes(payload in prop::collection::vec(any::<u8>(), 0..1024)) {
+            parse_all(&payload);
+        }
+
+        #[test]
+        fn test_parse_xml_fragments(payload in xml_fragments()) {
+            parse_all(&payload);
+        }
+    }
 
     #[test]
     fn test_graphics_reply_parse() {
@@ -450,6 +899,326 @@ mod tests {
         assert_eq!(expectation, GraphicsReplyData::from_xml(fixture).unwrap());
     }
 
+    #[test]
+    fn test_channel_direction_conversion() {
+        for c in ControlMsgChannel::enum_iter() {
+            assert_eq!(ControlMsgChannel::try_from(MsgChannel::from(c)), Ok(c));
+            assert_eq!(
+                StatusMsgChannel::try_from(MsgChannel::from(c)),
+                Err(c.into())
+            );
+        }
+        for c in StatusMsgChannel::enum_iter() {
+            assert_eq!(StatusMsgChannel::try_from(MsgChannel::from(c)), Ok(c));
+            assert_eq!(
+                ControlMsgChannel::try_from(MsgChannel::from(c)),
+                Err(c.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_client_heartbeat_parse() {
+        // Formatted by the client with `%e`.
+        let fixture =
+            b"<heartbeat/><wss>1.234500e+07</wss><max_wss>2.147484e+09</max_wss>".to_vec();
+
+        assert_eq!(
+            ControlMessage::from_raw(ControlMsgChannel::Heartbeat, fixture).unwrap(),
+            ControlMessage::Heartbeat(Heartbeat {
+                wss: Some(12_345_000.0),
+                max_wss: Some(2_147_484_000.0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_is_urgent() {
+        let request = ControlMessage::ProcessControlRequest;
+        assert!(request(ProcessControlRequest::Quit).is_urgent());
+        assert!(request(ProcessControlRequest::Abort { reason: None }).is_urgent());
+        assert!(!request(ProcessControlRequest::Suspend {
+            reason: SuspendReason::empty(),
+        })
+        .is_urgent());
+        assert!(!request(ProcessControlRequest::Resume).is_urgent());
+        assert!(!ControlMessage::GraphicsRequest.is_urgent());
+        assert!(!ControlMessage::Heartbeat(Heartbeat {
+            wss: None,
+            max_wss: None,
+        })
+        .is_urgent());
+        assert!(!ControlMessage::TrickleDown(TrickleDownData {
+            have_new_trickle_down: true,
+            upload_file_status: false,
+        })
+        .is_urgent());
+    }
+
+    #[test]
+    fn test_abort_parse() {
+        assert_eq!(
+            ProcessControlRequest::Abort { reason: None },
+            ProcessControlRequest::from_xml(b"<abort/>").unwrap()
+        );
+        assert_eq!(
+            ProcessControlRequest::Abort {
+                reason: Some("disk limit exceeded".into())
+            },
+            ProcessControlRequest::from_xml(b"<abort>disk limit exceeded</abort>").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_network_requests_parse() {
+        assert_eq!(
+            ProcessControlRequest::SuspendNetwork,
+            ProcessControlRequest::from_xml(b"<suspend_network/>").unwrap()
+        );
+        assert_eq!(
+            ProcessControlRequest::ResumeNetwork,
+            ProcessControlRequest::from_xml(b"<resume_network/>").unwrap()
+        );
+        assert_eq!(
+            ProcessControlRequest::SuspendNetwork.to_xml(),
+            b"<suspend_network/>".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_abort_reason_escaped() {
+        let request = ProcessControlRequest::Abort {
+            reason: Some("disk < 1 GB & quota".into()),
+        };
+        assert_eq!(
+            request.to_xml(),
+            b"<abort>disk &lt; 1 GB &amp; quota</abort>".to_vec()
+        );
+        assert_eq!(
+            ProcessControlRequest::from_xml(&request.to_xml()).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn test_suspend_reasons_parse() {
+        for (code, reason) in [
+            (1, SuspendReason::BATTERIES),
+            (2, SuspendReason::USER_ACTIVE),
+            (4, SuspendReason::USER_REQ),
+            (16, SuspendReason::BENCHMARKS),
+            (18, SuspendReason::USER_ACTIVE | SuspendReason::BENCHMARKS),
+            (4096, SuspendReason::OS),
+            (4097, SuspendReason::WIFI_STATE),
+            (4100, SuspendReason::NO_GUI_KEEPALIVE),
+            (1 << 20 | 8, SuspendReason::from_bits_retain(1 << 20 | 8)),
+        ] {
+            let request = ProcessControlRequest::from_xml(
+                format!(
+                    "<suspend><suspend_reason>{}</suspend_reason></suspend>",
+                    code
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(request, ProcessControlRequest::Suspend { reason });
+            assert_eq!(
+                ProcessControlRequest::from_xml(&request.to_xml()).unwrap(),
+                request
+            );
+        }
+
+        assert_eq!(
+            ProcessControlRequest::from_xml(b"<suspend/>").unwrap(),
+            ProcessControlRequest::Suspend {
+                reason: SuspendReason::empty()
+            }
+        );
+        assert_ne!(
+            SuspendReason::from_raw(4097),
+            SuspendReason::BATTERIES | SuspendReason::OS
+        );
+        assert!(!SuspendReason::from_raw(4097).contains(SuspendReason::BATTERIES));
+        assert!(ProcessControlRequest::from_xml(
+            b"<suspend><suspend_reason>-1</suspend_reason></suspend>"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_app_status_ignores_unknown_fields() {
+        let expectation = AppStatusData {
+            current_cpu_time: 10.5,
+            checkpoint_cpu_time: 9.0,
+            want_network: false,
+            fraction_done: 0.75,
+            other_pid: None,
+            bytes_sent: None,
+            bytes_received: None,
+            working_set_size: None,
+            elapsed_time: None,
+        };
+
+        let fixture = "<current_cpu_time>10.5</current_cpu_time><future_field>42</future_field><checkpoint_cpu_time>9.0</checkpoint_cpu_time><future_group><nested/></future_group><fraction_done>0.75</fraction_done>".as_bytes();
+
+        assert_eq!(expectation, AppStatusData::from_xml(fixture).unwrap());
+    }
+
+    #[test]
+    fn test_app_status_traffic_round_trip() {
+        let mut status = AppStatusData {
+            current_cpu_time: 1.0,
+            checkpoint_cpu_time: 1.0,
+            want_network: true,
+            fraction_done: 0.5,
+            other_pid: None,
+            bytes_sent: None,
+            bytes_received: None,
+            working_set_size: None,
+            elapsed_time: None,
+        };
+        status.add_traffic(100.0, 2000.0);
+        status.add_traffic(24.0, 48.0);
+
+        let xml = String::from_utf8(status.to_xml()).unwrap();
+        assert!(xml.contains("<bytes_sent>124</bytes_sent>"), "{}", xml);
+        assert!(
+            xml.contains("<bytes_received>2048</bytes_received>"),
+            "{}",
+            xml
+        );
+        assert_eq!(AppStatusData::from_xml(xml.as_bytes()).unwrap(), status);
+    }
+
+    #[test]
+    fn test_app_status_approx_eq() {
+        let status = AppStatusData {
+            current_cpu_time: 10.0,
+            checkpoint_cpu_time: 5.0,
+            want_network: false,
+            fraction_done: 0.5,
+            other_pid: None,
+            bytes_sent: Some(100.0),
+            bytes_received: None,
+            working_set_size: None,
+            elapsed_time: None,
+        };
+        assert!(status.approx_eq(&status, 0.0));
+        assert_eq!(status, status.clone());
+
+        let mut close = status.clone();
+        close.current_cpu_time += 0.005;
+        close.fraction_done += 0.001;
+        assert_ne!(status, close);
+        assert!(status.approx_eq(&close, 0.01));
+
+        let mut far = status.clone();
+        far.fraction_done = 0.6;
+        assert!(!status.approx_eq(&far, 0.01));
+
+        let mut missing = status.clone();
+        missing.bytes_sent = None;
+        assert!(!status.approx_eq(&missing, f64::MAX));
+
+        let mut other_pid = status.clone();
+        other_pid.other_pid = Some(1);
+        assert!(!status.approx_eq(&other_pid, 1.0));
+    }
+
+    #[test]
+    fn test_serialized_len_matches_output() {
+        let messages = vec![
+            Message::Control(ControlMessage::ProcessControlRequest(
+                ProcessControlRequest::Abort {
+                    reason: Some("disk full".into()),
+                },
+            )),
+            Message::Control(ControlMessage::GraphicsRequest),
+            Message::Control(ControlMessage::Heartbeat(Heartbeat {
+                wss: Some(1024.5),
+                max_wss: None,
+            })),
+            Message::Status(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit,
+            )),
+            Message::Status(StatusMessage::GraphicsReply(GraphicsReplyData {
+                web_graphics_url: Some("http://localhost:8080".into()),
+                remote_desktop_addr: None,
+            })),
+            Message::Status(StatusMessage::AppStatus(AppStatusData {
+                current_cpu_time: 12.25,
+                checkpoint_cpu_time: 10.0,
+                want_network: true,
+                fraction_done: 0.125,
+                other_pid: Some(42),
+                bytes_sent: Some(1e6),
+                bytes_received: None,
+                working_set_size: Some(2.5e8),
+                elapsed_time: Some(14.0),
+            })),
+            Message::Status(StatusMessage::TrickleUp(TrickleUpData {
+                have_new_trickle_up: true,
+                have_new_upload_file: false,
+            })),
+        ];
+
+        for m in messages {
+            let len = m.serialized_len();
+            let (_, payload): (MsgChannel, Vec<u8>) = m.clone().into();
+            assert_eq!(len, payload.len(), "{:?}", m);
+        }
+    }
+
+    fn assert_malformed<T: std::fmt::Debug>(r: anyhow::Result<T>, field: &str) {
+        match r {
+            Ok(v) => panic!("{} must be rejected, got {:?}", field, v),
+            Err(e) => assert_eq!(
+                e.downcast_ref::<MalformedMessage>()
+                    .map(|e| e.field.as_str()),
+                Some(field),
+                "{}",
+                e
+            ),
+        }
+    }
+
+    #[test]
+    fn test_numeric_fields_rejected() {
+        for v in ["abc", "1e999", "-1", "NaN"] {
+            assert_malformed(
+                Heartbeat::from_xml(format!("<wss>{}</wss>", v).as_bytes()),
+                "wss",
+            );
+            assert_malformed(
+                Heartbeat::from_xml(format!("<max_wss>{}</max_wss>", v).as_bytes()),
+                "max_wss",
+            );
+            for field in [
+                "current_cpu_time",
+                "checkpoint_cpu_time",
+                "bytes_sent",
+                "bytes_received",
+                "working_set_size",
+                "elapsed_time",
+            ] {
+                let xml = format!("<fraction_done>0.5</fraction_done><{0}>{1}</{0}>", field, v);
+                assert_malformed(AppStatusData::from_xml(xml.as_bytes()), field);
+            }
+        }
+
+        for v in ["half", "1.5", "-0.1", "inf"] {
+            let xml = format!("<fraction_done>{}</fraction_done>", v);
+            assert_malformed(AppStatusData::from_xml(xml.as_bytes()), "fraction_done");
+        }
+        for v in ["x", "0", "99999999999999999999"] {
+            let xml = format!(
+                "<fraction_done>0.5</fraction_done><other_pid>{}</other_pid>",
+                v
+            );
+            assert_malformed(AppStatusData::from_xml(xml.as_bytes()), "other_pid");
+        }
+    }
+
     #[test]
     fn test_trickle_up_parse() {
         let expectation = TrickleUpData {
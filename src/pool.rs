@@ -0,0 +1,80 @@
+use crate::shmem::*;
+use std::collections::HashMap;
+
+/// Channels of several applications keyed by slot number, e.g. for a monitoring tool.
+#[derive(Default)]
+pub struct ChannelPool {
+    channels: HashMap<usize, SharedAppChannel>,
+}
+
+impl ChannelPool {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert(
+        &mut self,
+        slot: usize,
+        app_channel: SharedAppChannel,
+    ) -> Option<SharedAppChannel> {
+        self.channels.insert(slot, app_channel)
+    }
+
+    pub fn remove(&mut self, slot: usize) -> Option<SharedAppChannel> {
+        self.channels.remove(&slot)
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&SharedAppChannel> {
+        self.channels.get(&slot)
+    }
+
+    pub fn slots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.channels.keys().copied()
+    }
+
+    /// Channel occupancy of every slot in the pool.
+    pub fn stats(&self) -> HashMap<usize, Vec<ChannelStats>> {
+        self.channels
+            .iter()
+            .map(|(slot, c)| (*slot, c.stats()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_multiple_segments() {
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        let mut pool = ChannelPool::new();
+        for slot in 0..3 {
+            let c = MmapAppChannel::new(tmp.path().join(format!("slot{}", slot))).unwrap();
+            unsafe { c.force_unchecked((MsgChannel::AppStatus, vec![b'x'; slot + 1])) };
+            pool.insert(slot, Arc::new(c));
+        }
+
+        for slot in 0..3 {
+            assert_eq!(
+                pool.get(slot).unwrap().peek(MsgChannel::AppStatus),
+                Some(vec![b'x'; slot + 1])
+            );
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.len(), 3);
+        for (slot, channels) in stats {
+            let app_status = channels
+                .iter()
+                .find(|s| s.channel == MsgChannel::AppStatus)
+                .unwrap();
+            assert!(app_status.occupied);
+            assert_eq!(app_status.len, slot + 1);
+            assert_eq!(channels.iter().filter(|s| s.occupied).count(), 1);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{models::*, shmem::*};
+use std::{
+    convert::TryFrom,
+    marker::PhantomData,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Side of the connection a process is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Reads control messages and writes status messages.
+    App,
+    /// Reads status messages and writes control messages.
+    Client,
+}
+
+/// Attempt to write a message that only the other side is allowed to write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrongDirection {
+    pub role: Role,
+    pub channel: MsgChannel,
+}
+
+impl std::fmt::Display for WrongDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?} role cannot write to {:?}", self.role, self.channel)
+    }
+}
+
+impl std::error::Error for WrongDirection {}
+
+static DECLARED_ROLE: AtomicU8 = AtomicU8::new(0);
+
+/// Declare the role of this process. In debug builds with the `tracing` feature the `AppChannel` write methods,
+/// including the unchecked ones, then emit a warning for messages written in the other side's direction.
+/// Otherwise it is only recorded.
+pub fn declare_role(role: Role) {
+    let v = match role {
+        Role::App => 1,
+        Role::Client => 2,
+    };
+    DECLARED_ROLE.store(v, Ordering::Relaxed);
+}
+
+/// Forget the role passed to `declare_role`.
+pub fn clear_role() {
+    DECLARED_ROLE.store(0, Ordering::Relaxed);
+}
+
+/// Role passed to `declare_role`, if any.
+pub fn declared_role() -> Option<Role> {
+    match DECLARED_ROLE.load(Ordering::Relaxed) {
+        1 => Some(Role::App),
+        2 => Some(Role::Client),
+        _ => None,
+    }
+}
+
+fn wrong_direction(role: Role, channel: MsgChannel) -> Option<WrongDirection> {
+    let is_control = ControlMsgChannel::try_from(channel).is_ok();
+    match (role, is_control) {
+        (Role::App, false) | (Role::Client, true) => None,
+        _ => Some(WrongDirection { role, channel }),
+    }
+}
+
+/// Warn if writing to `channel` goes against the declared role.
+#[cfg(debug_assertions)]
+pub(crate) fn warn_wrong_direction(channel: MsgChannel) -> Option<WrongDirection> {
+    let e = wrong_direction(declared_role()?, channel)?;
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{}", e);
+    Some(e)
+}
+
+/// `AppChannel` restricted to the operations of a single role.
+pub struct RoledChannel {
+    app_channel: SharedAppChannel,
+    role: Role,
+}
+
+impl RoledChannel {
+    pub fn new(app_channel: SharedAppChannel, role: Role) -> Self {
+        Self { app_channel, role }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    fn check(&self, m: &Message) -> Result<(), WrongDirection> {
+        match wrong_direction(self.role, m.channel()) {
+            None => Ok(()),
+            Some(e) => Err(e),
+        }
+    }
+
+    /// Send a message in this role's direction. Returns it back if the channel is occupied.
+    pub fn push(&self, m: Message) -> Result<Option<Message>, WrongDirection> {
+        self.check(&m)?;
+        Ok(self.app_channel.push(m))
+    }
+
+    /// Overwrite a channel in this role's direction.
+    pub fn force(&self, m: Message) -> Result<(), WrongDirection> {
+        self.check(&m)?;
+        self.app_channel.force(m);
+        Ok(())
+    }
+
+    /// Receive a message written by the other side, if available.
+    pub fn receive(&self) -> Option<Message> {
+        match self.role {
+            Role::App => self.app_channel.pull_control().map(Message::Control),
+            Role::Client => self.app_channel.pull_status().map(Message::Status),
+        }
+    }
+}
+
+/// Invariant lifetime tying tokens to the channel they were issued with.
+type Brand<'id> = PhantomData<fn(&'id ()) -> &'id ()>;
+
+/// Proof of being one of the logical writers of a `GuardedChannel`. Tokens are only issued by
+/// `GuardedChannel::scope`, cannot be cloned and are only accepted by the channel they were issued with.
+#[derive(Debug)]
+pub struct WriterToken<'id> {
+    _brand: Brand<'id>,
+}
+
+/// Channel shared by several logical writers, e.g. threads acting as distinct apps against one segment. Writing
+/// requires a `WriterToken` of this channel, reading does not.
+///
+/// This is a type-level guard only: writers are not serialized beyond the usual transactions.
+pub struct GuardedChannel<'id> {
+    app_channel: SharedAppChannel,
+    _brand: Brand<'id>,
+}
+
+impl GuardedChannel<'_> {
+    /// Wrap `app_channel` and pass it to `f` with one token for each of the `writers`. The tokens carry a
+    /// lifetime unique to this call, so no other channel accepts them and no further ones can be obtained.
+    pub fn scope<R, F>(app_channel: SharedAppChannel, writers: usize, f: F) -> R
+    where
+        F: for<'id> FnOnce(GuardedChannel<'id>, Vec<WriterToken<'id>>) -> R,
+    {
+        let tokens = (0..writers)
+            .map(|_| WriterToken {
+                _brand: PhantomData,
+            })
+            .collect();
+        f(
+            GuardedChannel {
+                app_channel,
+                _brand: PhantomData,
+            },
+            tokens,
+        )
+    }
+}
+
+impl<'id> GuardedChannel<'id> {
+    /// Same as `AppChannel::push`.
+    #[must_use = "the returned message was not delivered"]
+    pub fn push(&self, _token: &WriterToken<'id>, m: Message) -> Option<Message> {
+        self.app_channel.push(m)
+    }
+
+    /// Same as `AppChannel::force`.
+    pub fn force(&self, _token: &WriterToken<'id>, m: Message) {
+        self.app_channel.force(m)
+    }
+
+    /// Take data from the specified channel.
+    pub fn receive(&self, c: MsgChannel) -> Option<Vec<u8>> {
+        self.app_channel.receive(c)
+    }
+
+    pub fn peek(&self, c: MsgChannel) -> Option<Vec<u8>> {
+        self.app_channel.peek(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_roles_write_own_direction() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let app = RoledChannel::new(app_channel.clone(), Role::App);
+        let client = RoledChannel::new(app_channel, Role::Client);
+
+        let control = Message::Control(ControlMessage::ProcessControlRequest(
+            ProcessControlRequest::Quit,
+        ));
+        let status = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        ));
+
+        assert_eq!(
+            app.push(control.clone()),
+            Err(WrongDirection {
+                role: Role::App,
+                channel: MsgChannel::ProcessControlRequest,
+            })
+        );
+        assert_eq!(
+            client.force(status.clone()),
+            Err(WrongDirection {
+                role: Role::Client,
+                channel: MsgChannel::ProcessControlReply,
+            })
+        );
+        assert_eq!(app.receive(), None);
+        assert_eq!(client.receive(), None);
+
+        assert_eq!(client.push(control.clone()), Ok(None));
+        assert_eq!(app.receive(), Some(control));
+        assert_eq!(app.push(status.clone()), Ok(None));
+        assert_eq!(client.receive(), Some(status));
+    }
+
+    #[test]
+    fn test_token_holders_write() {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        GuardedChannel::scope(app_channel, 2, |c, tokens| {
+            assert_eq!(tokens.len(), 2);
+
+            let reply = |r| Message::Status(StatusMessage::ProcessControlReply(r));
+            assert_eq!(c.push(&tokens[0], reply(ProcessControlReply::Quit)), None);
+            assert_eq!(
+                c.push(&tokens[1], reply(ProcessControlReply::Abort)),
+                Some(reply(ProcessControlReply::Abort))
+            );
+            c.force(&tokens[1], reply(ProcessControlReply::Abort));
+
+            // Readers need no token.
+            assert_eq!(
+                c.peek(MsgChannel::ProcessControlReply),
+                Some(b"<abort/>".to_vec())
+            );
+            assert_eq!(
+                c.receive(MsgChannel::ProcessControlReply),
+                Some(b"<abort/>".to_vec())
+            );
+            assert_eq!(c.receive(MsgChannel::ProcessControlReply), None);
+        });
+    }
+
+    #[test]
+    fn test_wrong_direction() {
+        // The declared role is process-wide, so it is tested in its own binary, see `tests/declared_role.rs`.
+        for c in MsgChannel::enum_iter() {
+            let control = ControlMsgChannel::try_from(c).is_ok();
+            assert_eq!(wrong_direction(Role::App, c).is_some(), control);
+            assert_eq!(wrong_direction(Role::Client, c).is_some(), !control);
+        }
+        assert_eq!(
+            wrong_direction(Role::App, MsgChannel::ProcessControlRequest),
+            Some(WrongDirection {
+                role: Role::App,
+                channel: MsgChannel::ProcessControlRequest,
+            })
+        );
+    }
+}
@@ -1,38 +1,133 @@
-use crate::models::*;
+use crate::{error, models::*};
 use libc::{self, c_char};
 use std::{
     self,
+    cell::{Cell, RefCell},
     cmp::min,
-    ffi::CStr,
+    convert::TryFrom,
+    ffi::{CStr, CString},
+    fs::File,
     io,
-    io::Write,
-    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
-    sync::{mpsc::channel, Arc, Mutex},
+    io::{Read, Write},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, PoisonError, TryLockError,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 const MSG_CHANNEL_SIZE: usize = 1024;
 
+/// Shortest sleep between polls of an empty channel, so that a zero interval does not spin a core.
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Bytes of every channel taken by the framing: the busy flag in front of the payload and its NUL terminator.
+const MSG_CHANNEL_FRAMING: usize = 2;
+
+// `MAX_PAYLOAD` is derived from the channel size, so a channel without room for the framing plus at least one
+// payload byte would silently drop every message instead of failing the build.
+const _: () = assert!(
+    MSG_CHANNEL_SIZE > MSG_CHANNEL_FRAMING,
+    "MSG_CHANNEL_SIZE must leave room for the busy flag, the NUL terminator and a payload"
+);
+
+// `c_char` is signed on x86 and unsigned on ARM. Same-width `as` casts keep the bit pattern either way, so bytes
+// above 127 survive the round trip; all conversions go through these two.
+fn to_c_char(b: u8) -> c_char {
+    b as c_char
+}
+
+fn from_c_char(c: c_char) -> u8 {
+    c as u8
+}
+
 #[repr(C)]
 pub struct MSG_CHANNEL {
     buf: [c_char; MSG_CHANNEL_SIZE],
 }
 
 impl MSG_CHANNEL {
+    /// Largest payload that fits into the channel: the buffer minus the busy flag and the trailing NUL.
+    pub const MAX_PAYLOAD: usize = MSG_CHANNEL_SIZE - MSG_CHANNEL_FRAMING;
+
     pub fn is_empty(&self) -> bool {
-        self.buf[0] == 0
+        self.flag() == 0
     }
 
     pub fn clear(&mut self) {
-        self.buf[0] = 0;
+        self.set_flag(0);
+    }
+
+    /// Busy flag as written by the other side, normally 0 or 1.
+    fn flag(&self) -> u8 {
+        from_c_char(self.buf[0])
+    }
+
+    fn set_flag(&mut self, v: u8) {
+        self.buf[0] = to_c_char(v);
+    }
+
+    /// Check the framing for debugging corrupt segments: the flag is 0 or 1 and an occupied channel holds a
+    /// non-empty payload ending in its NUL terminator within the buffer. The contents of an empty channel are
+    /// not looked at.
+    ///
+    /// Graphics requests are legitimately empty, see `AppChannel::health_check` for a check that knows the channel.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self.flag() {
+            0 => Ok(()),
+            1 => match self.buf[1..].iter().position(|b| *b == 0) {
+                None => Err("occupied channel has no terminator within the buffer"),
+                Some(0) => Err("occupied channel has an empty payload"),
+                Some(_) => Ok(()),
+            },
+            _ => Err("busy flag is neither 0 nor 1"),
+        }
+    }
+
+    /// `validate` for channel `c`, accepting the empty payload of a graphics request.
+    fn is_well_formed(&self, c: MsgChannel) -> bool {
+        match self.validate() {
+            Ok(()) => true,
+            Err(_) => c == MsgChannel::GraphicsRequest && self.flag() == 1 && self.buf[1] == 0,
+        }
+    }
+
+    /// Bytes a `push` can accept right now. A channel holds a single message, so this is all or nothing.
+    pub fn free_space(&self) -> usize {
+        if self.is_empty() {
+            Self::MAX_PAYLOAD
+        } else {
+            0
+        }
+    }
+
+    /// Payload up to its terminator, at most `MAX_PAYLOAD` bytes. Empty if the channel is empty.
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.is_empty() {
+            return &[];
+        }
+        // `c_char` and `u8` have the same size and alignment.
+        let buf =
+            unsafe { std::slice::from_raw_parts(self.buf.as_ptr() as *const u8, MSG_CHANNEL_SIZE) };
+        let payload = &buf[1..=Self::MAX_PAYLOAD];
+        let len = payload
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(Self::MAX_PAYLOAD);
+        &payload[..len]
     }
 
     pub fn peek(&self) -> Option<Vec<u8>> {
         if self.is_empty() {
             None
         } else {
-            let mut v: Vec<c_char> = (&self.buf[1..MSG_CHANNEL_SIZE - 2]).into();
-            v[MSG_CHANNEL_SIZE - 4] = 0;
-            Some(unsafe { CStr::from_ptr(v.as_ptr()) }.to_bytes().into())
+            Some(self.as_bytes().to_vec())
         }
     }
 
@@ -47,26 +142,21 @@ impl MSG_CHANNEL {
         T: Into<Vec<u8>>,
     {
         let v = msg.into();
-        self.buf[0] = 1;
-        for (i, e) in v
-            .iter()
-            .enumerate()
-            .take(min(v.len(), MSG_CHANNEL_SIZE - 2))
-        {
-            let c = *e as c_char;
-            self.buf[i + 1] = c;
-            if c == 0 {
-                break;
-            }
+        let len = min(v.len(), Self::MAX_PAYLOAD);
+        self.set_flag(1);
+        for (i, e) in v.iter().enumerate().take(len) {
+            self.buf[i + 1] = to_c_char(*e);
         }
-        self.buf[MSG_CHANNEL_SIZE - 1] = 0;
+        self.buf[len + 1] = 0;
     }
 
+    /// Push the message if the channel is empty and the payload fits into `MAX_PAYLOAD`, otherwise hand it back.
+    #[must_use = "the returned message was not delivered"]
     pub fn push<T>(&mut self, msg: T) -> Option<T>
     where
-        T: Into<Vec<u8>>,
+        T: AsRef<[u8]> + Into<Vec<u8>>,
     {
-        if self.is_empty() {
+        if self.is_empty() && msg.as_ref().len() <= Self::MAX_PAYLOAD {
             self.force_push(msg);
             None
         } else {
@@ -75,17 +165,23 @@ impl MSG_CHANNEL {
     }
 }
 
-impl Default for MSG_CHANNEL {
-    fn default() -> Self {
+impl MSG_CHANNEL {
+    /// Canonical empty channel: all bytes zero, as in a freshly created segment.
+    pub const fn zeroed() -> Self {
         Self {
             buf: [0; MSG_CHANNEL_SIZE],
         }
     }
 }
 
+impl Default for MSG_CHANNEL {
+    fn default() -> Self {
+        Self::zeroed()
+    }
+}
+
 /// ! On disk representation of the memory shared between client and application.
 #[repr(C)]
-#[derive(Default)]
 pub struct SHARED_MEM {
     pub process_control_request: MSG_CHANNEL,
     pub process_control_reply: MSG_CHANNEL,
@@ -97,7 +193,122 @@ pub struct SHARED_MEM {
     pub trickle_down: MSG_CHANNEL,
 }
 
+// The layout must match BOINC's `SHARED_MEM` so that the C++ client and the application agree on it.
+const _: () = {
+    assert!(std::mem::size_of::<MSG_CHANNEL>() == MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, process_control_request) == 0);
+    assert!(std::mem::offset_of!(SHARED_MEM, process_control_reply) == MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, graphics_request) == 2 * MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, graphics_reply) == 3 * MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, heartbeat) == 4 * MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, app_status) == 5 * MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, trickle_up) == 6 * MSG_CHANNEL_SIZE);
+    assert!(std::mem::offset_of!(SHARED_MEM, trickle_down) == 7 * MSG_CHANNEL_SIZE);
+    assert!(std::mem::size_of::<SHARED_MEM>() == 8 * MSG_CHANNEL_SIZE);
+};
+
+/// Smallest page size on the supported platforms.
+pub const PAGE_SIZE: usize = 4096;
+
+/// `SHARED_MEM` aligned and padded to whole pages, for buffers that are mapped or copied page by page.
+///
+/// The shared memory itself keeps BOINC's exact layout at the start of the buffer.
+#[repr(C, align(4096))]
+#[derive(Default)]
+pub struct PageAlignedSharedMem(pub SHARED_MEM);
+
+const _: () = {
+    assert!(std::mem::align_of::<PageAlignedSharedMem>() == PAGE_SIZE);
+    assert!(std::mem::size_of::<PageAlignedSharedMem>().is_multiple_of(PAGE_SIZE));
+};
+
+impl std::ops::Deref for PageAlignedSharedMem {
+    type Target = SHARED_MEM;
+
+    fn deref(&self) -> &SHARED_MEM {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for PageAlignedSharedMem {
+    fn deref_mut(&mut self) -> &mut SHARED_MEM {
+        &mut self.0
+    }
+}
+
+/// Shared memory contents are implausible, e.g. it was overwritten by something other than the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Corrupted;
+
+impl std::fmt::Display for Corrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "shared memory is corrupted")
+    }
+}
+
+impl std::error::Error for Corrupted {}
+
+/// A transaction panicked while holding the shared memory lock, so its contents may be half written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Poisoned;
+
+impl std::fmt::Display for Poisoned {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "shared memory lock is poisoned")
+    }
+}
+
+impl std::error::Error for Poisoned {}
+
+/// The client did not consume a trickle-up message in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrickleTimeout;
+
+impl std::fmt::Display for TrickleTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "trickle-up was not consumed in time")
+    }
+}
+
+impl std::error::Error for TrickleTimeout {}
+
+const TRICKLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl Default for SHARED_MEM {
+    fn default() -> Self {
+        Self::zeroed()
+    }
+}
+
 impl SHARED_MEM {
+    /// Canonical empty shared memory: all channels zeroed, as in a freshly created segment.
+    pub const fn zeroed() -> Self {
+        Self {
+            process_control_request: MSG_CHANNEL::zeroed(),
+            process_control_reply: MSG_CHANNEL::zeroed(),
+            graphics_request: MSG_CHANNEL::zeroed(),
+            graphics_reply: MSG_CHANNEL::zeroed(),
+            heartbeat: MSG_CHANNEL::zeroed(),
+            app_status: MSG_CHANNEL::zeroed(),
+            trickle_up: MSG_CHANNEL::zeroed(),
+            trickle_down: MSG_CHANNEL::zeroed(),
+        }
+    }
+
+    /// Whether no channel holds a message, e.g. in a segment nobody has written to yet.
+    pub fn is_empty(&self) -> bool {
+        MsgChannel::enum_iter().all(|c| self.get_channel(c).is_empty())
+    }
+
+    /// Detect memory that cannot have been written by the client: every busy flag holds something other than 0 or 1.
+    pub fn validate(&self) -> Result<(), Corrupted> {
+        if MsgChannel::enum_iter().all(|c| !matches!(self.get_channel(c).flag(), 0 | 1)) {
+            Err(Corrupted)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn get_channel(&self, m: MsgChannel) -> &MSG_CHANNEL {
         match m {
             MsgChannel::ProcessControlRequest => &self.process_control_request,
@@ -124,110 +335,585 @@ impl SHARED_MEM {
     }
 }
 
+/// Occupancy of a single channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub channel: MsgChannel,
+    pub occupied: bool,
+    /// Payload length in bytes, zero if the channel is empty.
+    pub len: usize,
+}
+
+/// Result of `AppChannel::health_check`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthReport {
+    /// The shared memory could be accessed, e.g. its lock is not poisoned.
+    pub readable: bool,
+    /// Channels with a busy flag other than 0 or 1, or an occupied channel without NUL terminator.
+    pub corrupt_channels: Vec<MsgChannel>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.readable && self.corrupt_channels.is_empty()
+    }
+}
+
+/// What `pull_control_with` does with a control payload that cannot be decoded, e.g. a request tag sent by a
+/// newer client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownControlPolicy {
+    /// Consume it and continue with the next channel.
+    Ignore,
+    /// Consume it and return the decoding error.
+    Error,
+    /// Return a process control request as `ControlMessage::Unknown`. Other channels are handled as with `Ignore`.
+    Passthrough,
+}
+
+/// Order in which `pull_control_by` and `poll_control_batched_by` check the control channels. The default puts
+/// process control requests first, so that quit and abort are never delayed by other traffic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PollPriority(Vec<ControlMsgChannel>);
+
+impl PollPriority {
+    /// Check `first` in the given order, then any remaining channels in the default order.
+    pub fn new(first: &[ControlMsgChannel]) -> Self {
+        let mut order = Vec::new();
+        for c in first.iter().copied().chain(ControlMsgChannel::enum_iter()) {
+            if !order.contains(&c) {
+                order.push(c);
+            }
+        }
+        Self(order)
+    }
+
+    pub fn channels(&self) -> &[ControlMsgChannel] {
+        &self.0
+    }
+}
+
+impl Default for PollPriority {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+/// Channel payload decoded as text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadText {
+    pub text: String,
+    /// `false` if the payload is not valid UTF-8, e.g. because it was observed in the middle of a write.
+    /// Callers should retry on the next tick rather than act on such text.
+    pub valid: bool,
+}
+
+impl PayloadText {
+    pub fn decode(b: &[u8]) -> Self {
+        match std::str::from_utf8(b) {
+            Ok(text) => Self {
+                text: text.into(),
+                valid: true,
+            },
+            Err(_) => Self {
+                text: String::from_utf8_lossy(b).into_owned(),
+                valid: false,
+            },
+        }
+    }
+}
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"BSHM";
+
+/// Multi-byte integers are always stored little-endian so that files stay readable across architectures.
+fn encode_u32(v: u32) -> [u8; 4] {
+    v.to_le_bytes()
+}
+
+fn decode_u32(b: [u8; 4]) -> u32 {
+    u32::from_le_bytes(b)
+}
+
+impl SHARED_MEM {
+    /// Dump the segment in a portable binary format: a magic header followed by each channel's
+    /// busy flag, little-endian `u32` payload length and payload.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(SNAPSHOT_MAGIC)?;
+        for c in MsgChannel::enum_iter() {
+            let channel = self.get_channel(c);
+            let payload = channel.peek().unwrap_or_default();
+            w.write_all(&[channel.flag()])?;
+            w.write_all(&encode_u32(payload.len() as u32))?;
+            w.write_all(&payload)?;
+        }
+        Ok(())
+    }
+
+    /// Load a segment previously dumped with `write_to`.
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a shared memory snapshot",
+            ));
+        }
+
+        let mut mem = Self::default();
+        for c in MsgChannel::enum_iter() {
+            let mut flag = [0; 1];
+            r.read_exact(&mut flag)?;
+            let mut len = [0; 4];
+            r.read_exact(&mut len)?;
+            let len = decode_u32(len) as usize;
+            if len > MSG_CHANNEL::MAX_PAYLOAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("payload of {:?} exceeds channel size", c),
+                ));
+            }
+            let mut payload = vec![0; len];
+            r.read_exact(&mut payload)?;
+
+            let channel = mem.get_channel_mut(c);
+            if flag[0] != 0 {
+                channel.force_push(payload);
+                channel.set_flag(flag[0]);
+            }
+        }
+        Ok(mem)
+    }
+}
+
 /// Represents a channel that can be used to send control commands and status messages back and forth between client and application.
 pub trait AppChannel: Send + Sync + 'static {
     /// Internal accessor for shared memory.
     fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM));
 
+    /// Like `transaction`, but fails instead of panicking if an earlier transaction panicked while holding the
+    /// lock. Implementations with a lock that can be poisoned override it.
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        self.transaction(f);
+        Ok(())
+    }
+
+    /// Internal accessor for a single channel. Implementations with finer grained locking override it so that
+    /// operations on different channels do not contend.
+    fn channel_transaction(&self, c: MsgChannel, f: &dyn Fn(&mut MSG_CHANNEL)) {
+        self.transaction(&|data| f(data.get_channel_mut(c)));
+    }
+
+    /// Check that the shared memory looks sane before decoding messages from it.
+    fn validate(&self) -> Result<(), Corrupted> {
+        self.with_transaction(|data| data.validate())
+    }
+
+    /// Probe whether the channel is usable, for monitoring. The segment layout has no version marker, it is
+    /// fixed at compile time, so only accessibility and the channel framing are checked.
+    fn health_check(&self) -> HealthReport {
+        let corrupt_channels = Cell::new(vec![]);
+        let probe = self.checked_transaction(&|data| {
+            corrupt_channels.set(
+                MsgChannel::enum_iter()
+                    .filter(|c| !data.get_channel(*c).is_well_formed(*c))
+                    .collect(),
+            )
+        });
+        HealthReport {
+            readable: probe.is_ok(),
+            corrupt_channels: corrupt_channels.into_inner(),
+        }
+    }
+
     /// Check if `MsgChannel` contains a message.
     fn is_empty(&self, c: MsgChannel) -> bool {
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
-            tx.send(data.get_channel(c).is_empty()).unwrap();
-        });
-        rx.recv().unwrap()
+        self.with_channel(c, |ch| ch.is_empty())
+    }
+
+    /// Bytes the channel can accept right now, see `MSG_CHANNEL::free_space`.
+    fn free_space(&self, c: MsgChannel) -> usize {
+        self.with_channel(c, |ch| ch.free_space())
+    }
+
+    /// Length of the payload in the channel, 0 if it is empty.
+    fn len_of(&self, c: MsgChannel) -> usize {
+        self.with_channel(c, |ch| ch.as_bytes().len())
+    }
+
+    /// Check in a single transaction if any of the given channels contains a message.
+    fn is_any_pending(&self, channels: &[MsgChannel]) -> bool {
+        self.with_transaction(|data| channels.iter().any(|c| !data.get_channel(*c).is_empty()))
+    }
+
+    /// Occupancy of every channel, collected in a single transaction.
+    fn stats(&self) -> Vec<ChannelStats> {
+        self.with_transaction(|data| {
+            MsgChannel::enum_iter()
+                .map(|c| {
+                    let payload = data.get_channel(c).peek();
+                    ChannelStats {
+                        channel: c,
+                        occupied: payload.is_some(),
+                        len: payload.map(|v| v.len()).unwrap_or(0),
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// `stats` as a compact JSON object keyed by channel name, e.g. for a monitoring endpoint.
+    #[cfg(feature = "serde")]
+    fn stats_json(&self) -> String {
+        let mut channels = serde_json::Map::new();
+        for s in self.stats() {
+            let mut v = serde_json::Map::new();
+            v.insert("occupied".to_string(), s.occupied.into());
+            v.insert("len".to_string(), s.len.into());
+            channels.insert(format!("{:?}", s.channel), serde_json::Value::Object(v));
+        }
+        serde_json::Value::Object(channels).to_string()
     }
 
     /// Check `MsgChannel` contents without extracting.
     fn peek(&self, c: MsgChannel) -> Option<Vec<u8>> {
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
-            tx.send(data.get_channel(c).peek()).unwrap();
-        });
-        rx.recv().unwrap()
+        self.with_channel(c, |ch| ch.peek())
+    }
+
+    /// Check `MsgChannel` contents as text without extracting.
+    fn peek_text(&self, c: MsgChannel) -> Option<PayloadText> {
+        self.peek(c).map(|v| PayloadText::decode(&v))
     }
 
     /// Extract data from the specified `MsgChannel`.
     fn receive(&self, c: MsgChannel) -> Option<Vec<u8>> {
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
-            tx.send(data.get_channel_mut(c).pop()).unwrap();
-        });
-        rx.recv().unwrap()
+        let v = self.with_channel(c, |ch| ch.pop());
+        #[cfg(feature = "tracing")]
+        if let Some(v) = &v {
+            tracing::debug!(channel = ?c, len = v.len(), "received");
+        }
+        v
+    }
+
+    /// Wait up to `timeout` for data in the specified `MsgChannel`, checking it every `poll`
+    /// (at least `MIN_POLL_INTERVAL`).
+    fn receive_timeout(&self, c: MsgChannel, timeout: Duration, poll: Duration) -> Option<Vec<u8>> {
+        self.receive_until(c, Instant::now() + timeout, poll)
+    }
+
+    /// Wait until `deadline` for data in the specified `MsgChannel`, checking it every `poll`
+    /// (at least `MIN_POLL_INTERVAL`).
+    fn receive_until(&self, c: MsgChannel, deadline: Instant, poll: Duration) -> Option<Vec<u8>> {
+        loop {
+            if let Some(v) = self.receive(c) {
+                return Some(v);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            thread::sleep(min(poll.max(MIN_POLL_INTERVAL), deadline - now));
+        }
     }
 
     /// Receive a new status message from any of the channels, if available
     fn pull_control(&self) -> Option<ControlMessage> {
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
-            for id in ControlMsgChannel::enum_iter() {
-                if let Some(v) = data.get_channel_mut(id.into()).pop() {
-                    tx.send(Some(ControlMessage::from_raw(id, v).unwrap()))
-                        .unwrap();
+        self.pull_control_by(&PollPriority::default())
+    }
+
+    /// Like `pull_control`, checking the channels in the order given by `priority`. Payloads that cannot be
+    /// decoded, e.g. with out-of-range values or unknown tags, are consumed and skipped.
+    fn pull_control_by(&self, priority: &PollPriority) -> Option<ControlMessage> {
+        // `Ignore` never reports an error.
+        self.pull_control_by_with(priority, UnknownControlPolicy::Ignore)
+            .unwrap_or(None)
+    }
+
+    /// Like `pull_control`, with `policy` deciding what happens to payloads that cannot be decoded.
+    fn pull_control_with(
+        &self,
+        policy: UnknownControlPolicy,
+    ) -> anyhow::Result<Option<ControlMessage>> {
+        self.pull_control_by_with(&PollPriority::default(), policy)
+    }
+
+    /// Like `pull_control_by`, with `policy` deciding what happens to payloads that cannot be decoded.
+    fn pull_control_by_with(
+        &self,
+        priority: &PollPriority,
+        policy: UnknownControlPolicy,
+    ) -> anyhow::Result<Option<ControlMessage>> {
+        loop {
+            let raw = self.with_transaction(|data| {
+                priority
+                    .channels()
+                    .iter()
+                    .find_map(|id| data.get_channel_mut((*id).into()).pop().map(|v| (*id, v)))
+            });
+            let (id, v) = match raw {
+                Some(raw) => raw,
+                None => return Ok(None),
+            };
+            match ControlMessage::from_raw(id, v.clone()) {
+                Ok(m) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(channel = ?m.channel(), "pulled control message");
+                    return Ok(Some(m));
+                }
+                Err(e) => match policy {
+                    UnknownControlPolicy::Error => return Err(e),
+                    UnknownControlPolicy::Passthrough
+                        if id == ControlMsgChannel::ProcessControlRequest =>
+                    {
+                        return Ok(Some(ControlMessage::Unknown(v)));
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// Receive up to `max` control messages in one transaction. Each control channel holds at most one message,
+    /// so there are never more than four, and the ones over `max` are left for the next call. Payloads that
+    /// cannot be decoded are left in place for `pull_control_with` to deal with and do not count towards `max`.
+    fn poll_control_batched(&self, max: usize) -> Vec<ControlMessage> {
+        self.poll_control_batched_by(max, &PollPriority::default())
+    }
+
+    /// Like `poll_control_batched`, returning messages in the order given by `priority`.
+    fn poll_control_batched_by(&self, max: usize, priority: &PollPriority) -> Vec<ControlMessage> {
+        self.with_transaction(|data| {
+            let mut out = Vec::new();
+            for id in priority.channels().iter().copied() {
+                if out.len() >= max {
                     break;
                 }
+                let ch = data.get_channel_mut(id.into());
+                if let Some(m) = ch.peek().and_then(|v| ControlMessage::from_raw(id, v).ok()) {
+                    ch.clear();
+                    out.push(m);
+                }
             }
-            tx.send(None).unwrap();
-        });
-        rx.recv().unwrap()
+            out
+        })
     }
 
-    /// Receive a new status message from any of the channels, if available
+    /// Receive a new status message from any of the channels, if available. Payloads that cannot be decoded are
+    /// consumed and skipped.
     fn pull_status(&self) -> Option<StatusMessage> {
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
+        loop {
+            let decoded = self.map_transaction(
+                |data| {
+                    StatusMsgChannel::enum_iter()
+                        .find_map(|id| data.get_channel_mut(id.into()).pop().map(|v| (id, v)))
+                },
+                |raw| raw.map(|(id, v)| StatusMessage::from_raw(id, v)),
+            );
+            match decoded {
+                None => return None,
+                Some(Ok(m)) => return Some(m),
+                Some(Err(_)) => continue,
+            }
+        }
+    }
+
+    /// Decode the control message that `pull_control` would return, without extracting it.
+    fn peek_control(&self) -> Option<ControlMessage> {
+        self.map_transaction(
+            |data| {
+                ControlMsgChannel::enum_iter()
+                    .find_map(|id| data.get_channel(id.into()).peek().map(|v| (id, v)))
+            },
+            |raw| raw.and_then(|(id, v)| ControlMessage::from_raw(id, v).ok()),
+        )
+    }
+
+    /// Decode the status message that `pull_status` would return, without extracting it.
+    fn peek_status(&self) -> Option<StatusMessage> {
+        self.map_transaction(
+            |data| {
+                StatusMsgChannel::enum_iter()
+                    .find_map(|id| data.get_channel(id.into()).peek().map(|v| (id, v)))
+            },
+            |raw| raw.and_then(|(id, v)| StatusMessage::from_raw(id, v).ok()),
+        )
+    }
+
+    /// Receive and decode messages from every status channel in one transaction.
+    ///
+    /// Payloads that fail to decode are still removed from their channels and appended to `undecodable`.
+    fn receive_all_status(
+        &self,
+        undecodable: &mut Vec<(StatusMsgChannel, Vec<u8>)>,
+    ) -> Vec<StatusMessage> {
+        self.with_transaction(|data| {
+            let mut decoded = Vec::new();
             for id in StatusMsgChannel::enum_iter() {
                 if let Some(v) = data.get_channel_mut(id.into()).pop() {
-                    tx.send(Some(StatusMessage::from_raw(id, v).unwrap()))
-                        .unwrap();
-                    break;
+                    match StatusMessage::from_raw(id, v.clone()) {
+                        Ok(m) => decoded.push(m),
+                        Err(_) => undecodable.push((id, v)),
+                    }
                 }
             }
-            tx.send(None).unwrap();
-        });
-        rx.recv().unwrap()
+            decoded
+        })
+    }
+
+    /// Receive and decode messages from every channel of both directions in one transaction.
+    ///
+    /// Payloads that fail to decode are still removed from their channels and appended to `undecodable`.
+    fn drain_typed(&self, undecodable: &mut Vec<(MsgChannel, Vec<u8>)>) -> Vec<Message> {
+        self.with_transaction(|data| {
+            let mut decoded = Vec::new();
+            for id in MsgChannel::enum_iter() {
+                if let Some(v) = data.get_channel_mut(id).pop() {
+                    match Message::from_raw(id, v.clone()) {
+                        Ok(m) => decoded.push(m),
+                        Err(_) => undecodable.push((id, v)),
+                    }
+                }
+            }
+            decoded
+        })
     }
 
     /// Clear channel contents.
     fn clear(&self, c: MsgChannel) {
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
-            data.get_channel_mut(c).clear();
-            tx.send(()).unwrap();
-        });
-        rx.recv().unwrap()
+        self.with_channel(c, |ch| ch.clear())
+    }
+
+    /// Move the message in `from` to `to` in one transaction, e.g. for a proxy. Nothing is moved if `to` is
+    /// occupied. Returns whether a message was moved.
+    fn forward(&self, from: MsgChannel, to: MsgChannel) -> bool {
+        self.with_transaction(|data| {
+            if !data.get_channel(to).is_empty() {
+                return false;
+            }
+            match data.get_channel_mut(from).pop() {
+                Some(v) => {
+                    data.get_channel_mut(to).force_push(v);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// Acknowledge a handled control request. Returns the reply back if the reply channel is occupied.
+    #[must_use = "the returned message was not delivered"]
+    fn reply_control(&self, reply: ProcessControlReply) -> Option<ProcessControlReply> {
+        self.push(Message::Status(StatusMessage::ProcessControlReply(reply)))
+            .map(|m| match m {
+                Message::Status(StatusMessage::ProcessControlReply(reply)) => reply,
+                _ => unreachable!(),
+            })
+    }
+
+    /// Report only the fraction done, clamped to `0..=1`, for applications that do not track CPU time.
+    /// Returns `false` if the previous status has not been consumed yet.
+    fn report_fraction_done(&self, fraction_done: f64) -> bool {
+        let fraction_done = clamp_fraction(fraction_done);
+        let v = format!("<fraction_done>{}</fraction_done>\n", fraction_done).into_bytes();
+        self.with_channel(MsgChannel::AppStatus, |ch| ch.push(v).is_none())
     }
 
     /// Send the data to the channel.
+    #[must_use = "the returned message was not delivered"]
     fn push(&self, m: Message) -> Option<Message> {
+        #[cfg(debug_assertions)]
+        crate::role::warn_wrong_direction(m.channel());
         let (c, v) = m.clone().into();
-        let (tx, rx) = channel();
-        self.transaction(&move |data| {
-            tx.send(data.get_channel_mut(c).push(v.clone())).unwrap();
-        });
-        rx.recv().unwrap().map(|_| m)
+        let rejected = self.with_channel(c, |ch| ch.push(v));
+        #[cfg(feature = "tracing")]
+        match &rejected {
+            None => tracing::debug!(channel = ?c, "push accepted"),
+            Some(_) => tracing::debug!(channel = ?c, "push rejected"),
+        }
+        rejected.map(|_| m)
+    }
+
+    /// Try to `push` up to `attempts` times, sleeping `backoff` after the first failure and doubling it after
+    /// each next one. Returns the message back if the channel stayed occupied.
+    #[must_use = "the returned message was not delivered"]
+    fn push_retry(&self, m: Message, attempts: usize, backoff: Duration) -> Result<(), Message> {
+        let mut m = m;
+        let mut backoff = backoff;
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            match self.push(m) {
+                None => return Ok(()),
+                Some(rejected) => m = rejected,
+            }
+        }
+        Err(m)
+    }
+
+    /// Send a trickle-up message and wait until the client has consumed it, both within `timeout`.
+    ///
+    /// BOINC does not acknowledge trickle-up messages, so the client taking the message off the channel is the
+    /// only confirmation of delivery there is.
+    fn send_trickle_up_confirmed(
+        &self,
+        t: &TrickleUpData,
+        timeout: Duration,
+    ) -> Result<(), TrickleTimeout> {
+        let deadline = Instant::now() + timeout;
+        let mut m = Message::Status(StatusMessage::TrickleUp(t.clone()));
+        while let Some(rejected) = self.push(m) {
+            m = rejected;
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TrickleTimeout);
+            }
+            thread::sleep(min(TRICKLE_POLL_INTERVAL, deadline - now));
+        }
+
+        while !self.is_empty(MsgChannel::TrickleUp) {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TrickleTimeout);
+            }
+            thread::sleep(min(TRICKLE_POLL_INTERVAL, deadline - now));
+        }
+        Ok(())
     }
 
     /// Send the data to the channel.
     ///
     /// # Safety
     /// This version does not check message validity and is thus marked unsafe.
+    #[must_use = "the returned message was not delivered"]
     unsafe fn push_unchecked(&self, m: (MsgChannel, Vec<u8>)) -> Option<(MsgChannel, Vec<u8>)> {
-        let (tx, rx) = channel();
-        let c = m.0;
-        let v = m.1;
-        self.transaction(&move |data| {
-            tx.send(data.get_channel_mut(c).push(v.clone())).unwrap();
-        });
-        rx.recv().unwrap().map(|v| (c, v))
+        let (c, v) = m;
+        #[cfg(debug_assertions)]
+        crate::role::warn_wrong_direction(c);
+        self.with_channel(c, |ch| ch.push(v)).map(|v| (c, v))
     }
 
     /// Overwrite channel contents.
     fn force(&self, m: Message) {
+        #[cfg(debug_assertions)]
+        crate::role::warn_wrong_direction(m.channel());
         let (c, v) = m.into();
-        self.transaction(&move |data| {
-            data.get_channel_mut(c).force_push(v.as_slice());
-        });
+        self.with_channel(c, |ch| ch.force_push(v))
+    }
+
+    /// Write the message only if the channel is empty, truncating it like `force`. Returns whether it was written.
+    fn force_if_empty(&self, m: Message) -> bool {
+        #[cfg(debug_assertions)]
+        crate::role::warn_wrong_direction(m.channel());
+        let (c, v) = m.into();
+        self.with_channel(c, |ch| {
+            let empty = ch.is_empty();
+            if empty {
+                ch.force_push(v);
+            }
+            empty
+        })
     }
 
     /// Overwrite channel contents.
@@ -235,80 +921,2191 @@ pub trait AppChannel: Send + Sync + 'static {
     /// # Safety
     /// This version does not check message validity and is thus marked unsafe.
     unsafe fn force_unchecked(&self, m: (MsgChannel, Vec<u8>)) {
-        let c = m.0;
-        let v = m.1;
-        self.transaction(&move |data| {
-            data.get_channel_mut(c).force_push(v.clone());
+        let (c, v) = m;
+        #[cfg(debug_assertions)]
+        crate::role::warn_wrong_direction(c);
+        self.with_channel(c, |ch| ch.force_push(v))
+    }
+}
+
+/// Generic operations available on every `AppChannel`, including `SharedAppChannel`.
+pub trait AppChannelExt: AppChannel {
+    /// Run `f` against the shared memory in a single transaction and return its result.
+    ///
+    /// This is the building block for custom atomic sequences not covered by the provided helpers.
+    fn with_transaction<R>(&self, f: impl FnOnce(&mut SHARED_MEM) -> R) -> R {
+        let f = Cell::new(Some(f));
+        let out = Cell::new(None);
+        self.transaction(&|data| {
+            if let Some(f) = f.take() {
+                out.set(Some(f(data)));
+            }
+        });
+        out.into_inner().expect("transaction was not executed")
+    }
+
+    /// Single-channel counterpart of `with_transaction`, which does not contend with operations on other channels
+    /// where the implementation supports it.
+    fn with_channel<R>(&self, c: MsgChannel, f: impl FnOnce(&mut MSG_CHANNEL) -> R) -> R {
+        let f = Cell::new(Some(f));
+        let out = Cell::new(None);
+        self.channel_transaction(c, &|ch| {
+            if let Some(f) = f.take() {
+                out.set(Some(f(ch)));
+            }
         });
+        out.into_inner().expect("transaction was not executed")
+    }
+
+    /// Read raw data with `f` in a single transaction, then process it with `g` after the lock is released,
+    /// e.g. to decode payloads without holding up the other side.
+    fn map_transaction<T, R>(
+        &self,
+        f: impl FnOnce(&mut SHARED_MEM) -> T,
+        g: impl FnOnce(T) -> R,
+    ) -> R {
+        g(self.with_transaction(f))
+    }
+
+    /// Read-only counterpart of `with_transaction`, e.g. for monitoring.
+    fn observe<R>(&self, f: impl FnOnce(&SHARED_MEM) -> R) -> R {
+        self.with_transaction(|data| f(data))
+    }
+
+    /// Clear channel `c` only if it is occupied and `pred` accepts its payload, in one transaction. Returns whether
+    /// it was cleared.
+    fn clear_if(&self, c: MsgChannel, pred: impl Fn(&[u8]) -> bool) -> bool {
+        self.with_channel(c, |ch| {
+            let matches = !ch.is_empty() && pred(ch.as_bytes());
+            if matches {
+                ch.clear();
+            }
+            matches
+        })
+    }
+
+    /// Pop a process control request, let `f` handle it and write its reply, all in one transaction.
+    ///
+    /// Returns whether a request was handled. Undecodable requests are consumed without calling `f`.
+    fn handle_control<F: FnOnce(ControlMessage) -> ProcessControlReply>(&self, f: F) -> bool {
+        self.with_transaction(|data| {
+            let v = match data.process_control_request.pop() {
+                Some(v) => v,
+                None => return false,
+            };
+            let request =
+                match ControlMessage::from_raw(ControlMsgChannel::ProcessControlRequest, v) {
+                    Ok(request) => request,
+                    Err(_) => return false,
+                };
+            let (c, reply) = Message::Status(StatusMessage::ProcessControlReply(f(request))).into();
+            data.get_channel_mut(c).force_push(reply);
+            true
+        })
     }
 }
 
+impl<T: AppChannel + ?Sized> AppChannelExt for T {}
+
 #[derive(Default)]
 pub struct MemoryAppChannel(Mutex<SHARED_MEM>);
 
-impl AppChannel for MemoryAppChannel {
-    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
-        f(&mut *self.0.lock().unwrap());
+impl From<SHARED_MEM> for MemoryAppChannel {
+    fn from(mem: SHARED_MEM) -> Self {
+        Self(Mutex::new(mem))
     }
 }
 
+impl AppChannel for MemoryAppChannel {
+    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("transaction").entered();
+        f(&mut self.0.lock().unwrap());
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        let mut mem = self.0.lock().map_err(|_| Poisoned)?;
+        f(&mut mem);
+        Ok(())
+    }
+}
+
+/// In-process channel with a lock per `MSG_CHANNEL`, so that e.g. a heartbeat check does not wait for a status
+/// write. Whole-memory transactions lock every channel and work on a copy.
+#[derive(Default)]
+pub struct ShardedAppChannel([Mutex<MSG_CHANNEL>; 8]);
+
+impl AppChannel for ShardedAppChannel {
+    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+        self.checked_transaction(f).unwrap()
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        // Always locked in the same order, so concurrent transactions cannot deadlock.
+        let mut guards = self
+            .0
+            .iter()
+            .map(|m| m.lock().map_err(|_| Poisoned))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut mem = SHARED_MEM::zeroed();
+        for (c, g) in MsgChannel::enum_iter().zip(guards.iter()) {
+            mem.get_channel_mut(c).buf = g.buf;
+        }
+        f(&mut mem);
+        for (c, g) in MsgChannel::enum_iter().zip(guards.iter_mut()) {
+            g.buf = mem.get_channel(c).buf;
+        }
+        Ok(())
+    }
+
+    fn channel_transaction(&self, c: MsgChannel, f: &dyn Fn(&mut MSG_CHANNEL)) {
+        f(&mut self.0[c as usize].lock().unwrap());
+    }
+}
+
+/// In-process channel that wakes blocked receivers as soon as any transaction completes, instead of polling.
+#[derive(Default)]
+pub struct CondvarAppChannel {
+    mem: Mutex<SHARED_MEM>,
+    changed: Condvar,
+}
+
+impl CondvarAppChannel {
+    /// Take data from the specified `MsgChannel`, blocking until something is written to it.
+    pub fn recv_blocking(&self, c: MsgChannel) -> Vec<u8> {
+        let mut mem = self.mem.lock().unwrap();
+        loop {
+            if let Some(v) = mem.get_channel_mut(c).pop() {
+                return v;
+            }
+            mem = self.changed.wait(mem).unwrap();
+        }
+    }
+
+    /// Like `recv_blocking`, giving up after `timeout`.
+    pub fn recv_timeout(&self, c: MsgChannel, timeout: Duration) -> Option<Vec<u8>> {
+        self.recv_until(c, Instant::now() + timeout)
+    }
+
+    fn recv_until(&self, c: MsgChannel, deadline: Instant) -> Option<Vec<u8>> {
+        let mut mem = self.mem.lock().unwrap();
+        loop {
+            if let Some(v) = mem.get_channel_mut(c).pop() {
+                return Some(v);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            mem = self.changed.wait_timeout(mem, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl AppChannel for CondvarAppChannel {
+    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("transaction").entered();
+        f(&mut self.mem.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        let mut mem = self.mem.lock().map_err(|_| Poisoned)?;
+        f(&mut mem);
+        drop(mem);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Woken by the write instead of polling, `poll` is ignored.
+    fn receive_until(&self, c: MsgChannel, deadline: Instant, _poll: Duration) -> Option<Vec<u8>> {
+        self.recv_until(c, deadline)
+    }
+}
+
+/// Decorator making every transaction take at least `delay`, to test how an application copes with slow shared
+/// memory on an overloaded host. The delay is spent while holding the wrapped channel's lock.
+pub struct DelayedAppChannel {
+    inner: SharedAppChannel,
+    delay: Duration,
+}
+
+impl DelayedAppChannel {
+    pub fn new(inner: SharedAppChannel, delay: Duration) -> Self {
+        Self { inner, delay }
+    }
+}
+
+impl AppChannel for DelayedAppChannel {
+    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+        self.inner.transaction(&|data| {
+            thread::sleep(self.delay);
+            f(data)
+        })
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        self.inner.checked_transaction(&|data| {
+            thread::sleep(self.delay);
+            f(data)
+        })
+    }
+
+    fn channel_transaction(&self, c: MsgChannel, f: &dyn Fn(&mut MSG_CHANNEL)) {
+        self.inner.channel_transaction(c, &|ch| {
+            thread::sleep(self.delay);
+            f(ch)
+        })
+    }
+}
+
+/// Fraction done as the client accepts it: clamped to `0..=1`, with NaN reported as no progress.
+pub(crate) fn clamp_fraction(fraction_done: f64) -> f64 {
+    if fraction_done.is_nan() {
+        0.0
+    } else {
+        fraction_done.clamp(0.0, 1.0)
+    }
+}
+
+/// Repeat a system call interrupted by a signal (`EINTR`) instead of failing.
+fn retry_eintr<T>(mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    loop {
+        match f() {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            r => return r,
+        }
+    }
+}
+
+/// Failure of a system call, keeping the original error (and errno) as the source.
+#[derive(Debug)]
+struct SyscallError {
+    call: &'static str,
+    source: io::Error,
+}
+
+impl std::fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} failed: {}", self.call, self.source)
+    }
+}
+
+impl std::error::Error for SyscallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn syscall_error(call: &'static str, source: io::Error) -> io::Error {
+    io::Error::new(source.kind(), SyscallError { call, source })
+}
+
+/// Pointer to a mapped `SHARED_MEM`.
+///
+/// Invariants: the mapping is created by `MmapAppChannel::new`, `MmapAppChannel::open_named` or
+/// `MmapAppChannel::remap`, stays valid until the owning channel is dropped or remapped, and is exclusively owned
+/// by that channel. It is only dereferenced or replaced while the channel's mutex is held, so moving it to another
+/// thread cannot introduce unsynchronized access from this process.
+struct MmapPtr(*mut SHARED_MEM);
+
+unsafe impl Send for MmapPtr {}
+
+const SHMEM_SIZE: usize = std::mem::size_of::<SHARED_MEM>();
+
+impl MmapPtr {
+    fn check_regular_file(path: &Path) -> io::Result<()> {
+        // Checked before opening, as opening e.g. a FIFO would block and other special files fail later in `mmap`.
+        if let Ok(md) = std::fs::metadata(path) {
+            if !md.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a regular file", path.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn map(path: &Path) -> io::Result<Self> {
+        Self::check_regular_file(path)?;
+
+        let mut f = retry_eintr(|| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o666)
+                .open(path)
+        })
+        .map_err(|e| syscall_error("open", e))?;
+
+        let md = retry_eintr(|| f.metadata()).map_err(|e| syscall_error("fstat", e))?;
+
+        if md.len() < SHMEM_SIZE as u64 {
+            // `write_all` already retries interrupted writes.
+            f.write_all(&[0; SHMEM_SIZE])
+                .map_err(|e| syscall_error("write", e))?;
+        }
+
+        Self::map_file(&f, 0)
+    }
+
+    /// Map a segment at `offset` within an existing file, which must be page aligned and leave room for it.
+    fn map_at(path: &Path, offset: u64) -> io::Result<Self> {
+        Self::check_regular_file(path)?;
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        if page_size == 0 || !offset.is_multiple_of(page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "offset {} is not a multiple of the page size {}",
+                    offset, page_size
+                ),
+            ));
+        }
+
+        let f = retry_eintr(|| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .read(true)
+                .open(path)
+        })
+        .map_err(|e| syscall_error("open", e))?;
+
+        let md = retry_eintr(|| f.metadata()).map_err(|e| syscall_error("fstat", e))?;
+
+        if md.len() < offset.saturating_add(SHMEM_SIZE as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is too small for a segment at offset {}",
+                    path.display(),
+                    offset
+                ),
+            ));
+        }
+
+        Self::map_file(&f, offset)
+    }
+
+    fn map_named(name: &CStr, create: bool) -> io::Result<Self> {
+        let mut flags = libc::O_RDWR;
+        if create {
+            flags |= libc::O_CREAT;
+        }
+
+        let fd = retry_eintr(
+            || match unsafe { libc::shm_open(name.as_ptr(), flags, 0o666) } {
+                fd if fd < 0 => Err(io::Error::last_os_error()),
+                fd => Ok(fd),
+            },
+        )
+        .map_err(|e| syscall_error("shm_open", e))?;
+        let f = unsafe { File::from_raw_fd(fd) };
+
+        let md = retry_eintr(|| f.metadata()).map_err(|e| syscall_error("fstat", e))?;
+
+        if md.len() < SHMEM_SIZE as u64 {
+            retry_eintr(|| f.set_len(SHMEM_SIZE as u64))
+                .map_err(|e| syscall_error("ftruncate", e))?;
+        }
+
+        Self::map_file(&f, 0)
+    }
+
+    fn map_file(f: &File, offset: u64) -> io::Result<Self> {
+        let shmem = retry_eintr(|| {
+            let shmem = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    SHMEM_SIZE,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_FILE | libc::MAP_SHARED,
+                    f.as_raw_fd(),
+                    offset as libc::off_t,
+                )
+            };
+            if shmem == libc::MAP_FAILED {
+                // Capture errno before any other call can clobber it.
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(shmem)
+            }
+        })
+        .map_err(|e| syscall_error("mmap", e))?;
+        if shmem.is_null() {
+            return Err(syscall_error(
+                "mmap",
+                io::Error::other("returned a null mapping"),
+            ));
+        }
+
+        let ptr = MmapPtr(shmem as *mut SHARED_MEM);
+        // The segment is small and polled constantly. The hint is best effort, a refusal is harmless.
+        let _ = ptr.advise(MmapAdvice::WillNeed);
+        Ok(ptr)
+    }
+
+    fn advise(&self, advice: MmapAdvice) -> io::Result<()> {
+        let advice = match advice {
+            MmapAdvice::Normal => libc::MADV_NORMAL,
+            MmapAdvice::Random => libc::MADV_RANDOM,
+            MmapAdvice::Sequential => libc::MADV_SEQUENTIAL,
+            MmapAdvice::WillNeed => libc::MADV_WILLNEED,
+        };
+        if unsafe { libc::madvise(self.0 as *mut libc::c_void, SHMEM_SIZE, advice) } != 0 {
+            return Err(syscall_error("madvise", io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    unsafe fn unmap(&self) {
+        libc::munmap(self.0 as *mut libc::c_void, SHMEM_SIZE);
+    }
+}
+
+/// Access pattern hint passed to `madvise` for the mapped segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmapAdvice {
+    Normal,
+    Random,
+    Sequential,
+    WillNeed,
+}
+
+enum Backing {
+    File {
+        path: PathBuf,
+        offset: u64,
+    },
+    /// POSIX named shared memory, unlinked on drop by the handle that created it unless leaked.
+    Named {
+        name: CString,
+        owner: AtomicBool,
+    },
+}
+
 /// Wrapper to operate on shared mapped memory.
-pub struct MmapAppChannel(Mutex<*mut SHARED_MEM>);
+pub struct MmapAppChannel {
+    backing: Backing,
+    ptr: Mutex<MmapPtr>,
+}
 
 impl Drop for MmapAppChannel {
     fn drop(&mut self) {
+        // Unmap under the same lock as `transaction`, also after a transaction panicked and poisoned it.
+        let p = self.ptr.lock().unwrap_or_else(PoisonError::into_inner);
         unsafe {
-            libc::munmap(
-                *self.0.lock().unwrap() as *mut libc::c_void,
-                std::mem::size_of::<SHARED_MEM>(),
-            );
+            p.unmap();
+        }
+        drop(p);
+        if let Backing::Named { name, owner } = &self.backing {
+            if owner.load(Ordering::SeqCst) {
+                unsafe {
+                    libc::shm_unlink(name.as_ptr());
+                }
+            }
         }
     }
 }
 
 impl AppChannel for MmapAppChannel {
     fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
-        let mut p = self.0.lock().unwrap();
-        f(unsafe { &mut **p })
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("transaction").entered();
+        let mut p = self.ptr.lock().unwrap();
+        f(unsafe { &mut *p.0 })
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        let mut p = self.ptr.lock().map_err(|_| Poisoned)?;
+        f(unsafe { &mut *p.0 });
+        Ok(())
     }
 }
 
 impl MmapAppChannel {
-    pub fn new<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
-        let mut f = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .mode(0o666)
-            .open(path)?;
-
-        const SZ: usize = std::mem::size_of::<SHARED_MEM>();
-        let md = f.metadata()?;
-
-        if md.len() < SZ as u64 {
-            f.write_all(&[0; SZ])?;
-        }
-
-        let shmem = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                SZ,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_FILE | libc::MAP_SHARED,
-                f.as_raw_fd(),
-                0,
-            )
+    pub fn new<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let ptr = Mutex::new(MmapPtr::map(&path)?);
+        Ok(Self {
+            backing: Backing::File { path, offset: 0 },
+            ptr,
+        })
+    }
+
+    /// Map a segment at `offset` within a larger file that packs several of them. Unlike `new`, the file is
+    /// neither created nor extended: it must already hold the whole segment, and `offset` must be page aligned.
+    pub fn new_at_offset<P: AsRef<Path>>(path: P, offset: u64) -> error::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let ptr = Mutex::new(MmapPtr::map_at(&path, offset)?);
+        Ok(Self {
+            backing: Backing::File { path, offset },
+            ptr,
+        })
+    }
+
+    /// Open POSIX named shared memory (`shm_open`). The name must start with a slash.
+    ///
+    /// A handle opened with `create` owns the segment and unlinks it on drop, other handles never do.
+    pub fn open_named(name: &str, create: bool) -> error::Result<Self> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let ptr = Mutex::new(MmapPtr::map_named(&name, create)?);
+        Ok(Self {
+            backing: Backing::Named {
+                name,
+                owner: AtomicBool::new(create),
+            },
+            ptr,
+        })
+    }
+
+    /// Keep a named segment alive after this handle is dropped, e.g. for a child process to inherit it.
+    pub fn leak(&self) {
+        if let Backing::Named { owner, .. } = &self.backing {
+            owner.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Same as `transaction`, but gives up with `WouldBlock` if the lock cannot be acquired within `timeout`,
+    /// and fails instead of panicking if an earlier transaction panicked while holding it.
+    pub fn try_transaction(
+        &self,
+        timeout: Duration,
+        f: &dyn Fn(&mut SHARED_MEM),
+    ) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut p = loop {
+            match self.ptr.try_lock() {
+                Ok(p) => break p,
+                Err(TryLockError::Poisoned(_)) => {
+                    return Err(io::Error::other(
+                        "shared memory lock poisoned by a panicked transaction",
+                    ))
+                }
+                Err(TryLockError::WouldBlock) => {}
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "timed out waiting for the shared memory lock",
+                ));
+            }
+            thread::sleep(min(Duration::from_millis(1), deadline - now));
+        };
+        f(unsafe { &mut *p.0 });
+        Ok(())
+    }
+
+    /// Advise the kernel how the mapping is going to be accessed. `new` already requests `WillNeed`.
+    pub fn advise(&self, advice: MmapAdvice) -> io::Result<()> {
+        self.ptr.lock().unwrap().advise(advice)
+    }
+
+    /// Map the backing file again, e.g. after the client has recreated it on task restart.
+    pub fn remap(&self) -> io::Result<()> {
+        let mut p = self.ptr.lock().unwrap();
+        let new = match &self.backing {
+            Backing::File { path, offset: 0 } => MmapPtr::map(path)?,
+            Backing::File { path, offset } => MmapPtr::map_at(path, *offset)?,
+            Backing::Named { name, .. } => MmapPtr::map_named(name, false)?,
         };
+        unsafe {
+            p.unmap();
+        }
+        *p = new;
+        Ok(())
+    }
+}
+
+/// Channel over two segments with one direction each: control messages live in `control` (client to app) and
+/// status messages in `status` (app to client). The channels of the other direction in each segment are unused.
+pub struct SplitAppChannel {
+    control: MmapAppChannel,
+    status: MmapAppChannel,
+}
+
+fn is_control(c: MsgChannel) -> bool {
+    ControlMsgChannel::try_from(c).is_ok()
+}
 
-        if shmem == libc::MAP_FAILED {
-            return Err(io::Error::last_os_error());
+impl SplitAppChannel {
+    pub fn new(control: MmapAppChannel, status: MmapAppChannel) -> Self {
+        Self { control, status }
+    }
+
+    fn segment(&self, c: MsgChannel) -> &MmapAppChannel {
+        if is_control(c) {
+            &self.control
+        } else {
+            &self.status
         }
+    }
+}
 
-        Ok(MmapAppChannel(Mutex::new(shmem as *mut SHARED_MEM)))
+/// Let `f` work on a copy combining the control channels of one segment with the status channels of the other.
+fn merged_transaction(
+    control: &mut SHARED_MEM,
+    status: &mut SHARED_MEM,
+    f: &dyn Fn(&mut SHARED_MEM),
+) {
+    let mut mem = SHARED_MEM::zeroed();
+    for c in MsgChannel::enum_iter() {
+        let src = if is_control(c) { &*control } else { &*status };
+        mem.get_channel_mut(c).buf = src.get_channel(c).buf;
+    }
+    f(&mut mem);
+    for c in MsgChannel::enum_iter() {
+        let dst = if is_control(c) {
+            &mut *control
+        } else {
+            &mut *status
+        };
+        dst.get_channel_mut(c).buf = mem.get_channel(c).buf;
     }
 }
 
-unsafe impl Send for MmapAppChannel {}
-unsafe impl Sync for MmapAppChannel {}
+impl AppChannel for SplitAppChannel {
+    fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+        // Always locked control first, so concurrent transactions cannot deadlock.
+        self.control.with_transaction(|control| {
+            self.status
+                .with_transaction(|status| merged_transaction(control, status, f))
+        })
+    }
+
+    fn checked_transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) -> Result<(), Poisoned> {
+        let status_probe = Cell::new(Ok(()));
+        self.control.checked_transaction(&|control| {
+            let control = RefCell::new(control);
+            status_probe.set(self.status.checked_transaction(&|status| {
+                merged_transaction(&mut control.borrow_mut(), status, f)
+            }))
+        })?;
+        status_probe.get()
+    }
+
+    fn channel_transaction(&self, c: MsgChannel, f: &dyn Fn(&mut MSG_CHANNEL)) {
+        self.segment(c).channel_transaction(c, f)
+    }
+}
 
 pub type SharedAppChannel = Arc<dyn AppChannel + Send + Sync + 'static>;
+
+/// Name of the shared memory file the client creates in the slot directory.
+pub const MMAP_FILE_NAME: &str = "boinc_mmap_file";
+
+/// How `open_app_channel` connected to the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelMode {
+    /// Running under the client, communicating through the slot's mapped file.
+    Mmap,
+    /// Running without a client, messages stay in process memory.
+    Standalone,
+}
+
+/// Open the channel in the current slot directory, or a standalone one if the client did not create it.
+///
+/// The selected mode is logged at info level with the `tracing` feature and returned to the caller.
+pub fn open_app_channel() -> error::Result<(SharedAppChannel, ChannelMode)> {
+    open_app_channel_at(MMAP_FILE_NAME)
+}
+
+/// Same as `open_app_channel` with an explicit path to the shared memory file.
+pub fn open_app_channel_at<P: AsRef<Path>>(
+    path: P,
+) -> error::Result<(SharedAppChannel, ChannelMode)> {
+    let path = path.as_ref();
+    let (app_channel, mode): (SharedAppChannel, _) = if path.exists() {
+        (Arc::new(MmapAppChannel::new(path)?), ChannelMode::Mmap)
+    } else {
+        (
+            Arc::new(MemoryAppChannel::default()),
+            ChannelMode::Standalone,
+        )
+    };
+    #[cfg(feature = "tracing")]
+    tracing::info!(mode = ?mode, path = %path.display(), "selected channel mode");
+    Ok((app_channel, mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::sync::mpsc::channel;
+
+    fn text_payload() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(1..=255u8, 0..=MSG_CHANNEL::MAX_PAYLOAD)
+    }
+
+    fn binary_payload() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(
+            prop::sample::select(vec![0u8, 1, b'<', 0x7f, 0x80, 0xff]),
+            0..=64,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn test_force_push_pop_round_trip(payload in text_payload()) {
+            let mut c = MSG_CHANNEL::default();
+            c.force_push(payload.clone());
+            prop_assert_eq!(c.pop(), Some(payload));
+            prop_assert!(c.is_empty());
+        }
+
+        #[test]
+        fn test_embedded_zero_terminates_payload(payload in binary_payload()) {
+            // Messages are NUL-terminated, so anything after the first zero byte is dropped.
+            let expected = payload.split(|b| *b == 0).next().unwrap().to_vec();
+
+            let mut c = MSG_CHANNEL::default();
+            c.force_push(payload);
+            prop_assert_eq!(c.pop(), Some(expected));
+        }
+
+        #[test]
+        fn test_push_onto_occupied_is_noop(first in text_payload(), second in text_payload()) {
+            let mut c = MSG_CHANNEL::default();
+            c.force_push(first.clone());
+            prop_assert_eq!(c.push(second.clone()), Some(second));
+            prop_assert_eq!(c.pop(), Some(first));
+        }
+    }
+
+    #[test]
+    fn test_boinc_layout() {
+        let mem = SHARED_MEM::default();
+        let base = &mem as *const SHARED_MEM as usize;
+        let offsets = MsgChannel::enum_iter()
+            .map(|c| mem.get_channel(c) as *const MSG_CHANNEL as usize - base)
+            .collect::<Vec<_>>();
+
+        assert_eq!(offsets, vec![0, 1024, 2048, 3072, 4096, 5120, 6144, 7168]);
+        assert_eq!(std::mem::size_of::<SHARED_MEM>(), 8192);
+    }
+
+    #[test]
+    fn test_page_aligned_layout() {
+        assert!(std::mem::size_of::<PageAlignedSharedMem>().is_multiple_of(PAGE_SIZE));
+        assert!(std::mem::size_of::<PageAlignedSharedMem>() >= std::mem::size_of::<SHARED_MEM>());
+
+        let mut mem = Box::<PageAlignedSharedMem>::default();
+        assert!((&*mem as *const PageAlignedSharedMem as usize).is_multiple_of(PAGE_SIZE));
+
+        mem.heartbeat.force_push("<wss>1</wss>");
+        assert_eq!(mem.0.heartbeat.peek(), Some(b"<wss>1</wss>".to_vec()));
+    }
+
+    #[test]
+    fn test_max_payload_round_trip() {
+        let mut c = MSG_CHANNEL::default();
+        let payload = vec![b'a'; MSG_CHANNEL::MAX_PAYLOAD];
+
+        assert_eq!(c.push(payload.clone()), None);
+        assert_eq!(c.pop(), Some(payload));
+    }
+
+    #[test]
+    fn test_as_bytes_matches_peek() {
+        let mut ch = MSG_CHANNEL::default();
+        assert_eq!(ch.as_bytes(), b"");
+
+        ch.force_push("<fraction_done>0.5</fraction_done>");
+        assert_eq!(Some(ch.as_bytes().to_vec()), ch.peek());
+
+        ch.force_push(vec![b'x'; MSG_CHANNEL::MAX_PAYLOAD + 10]);
+        assert_eq!(ch.as_bytes().len(), MSG_CHANNEL::MAX_PAYLOAD);
+        assert_eq!(Some(ch.as_bytes().to_vec()), ch.peek());
+    }
+
+    #[test]
+    fn test_oversized_payload_rejected() {
+        let mut c = MSG_CHANNEL::default();
+        let payload = vec![b'a'; MSG_CHANNEL::MAX_PAYLOAD + 1];
+
+        assert_eq!(c.push(payload.clone()), Some(payload));
+        assert!(c.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut mem = SHARED_MEM::default();
+        mem.heartbeat.force_push("<wss>1024</wss>");
+        mem.app_status
+            .force_push("<fraction_done>0.5</fraction_done>");
+
+        let mut snapshot = Vec::new();
+        mem.write_to(&mut snapshot).unwrap();
+
+        let c = MemoryAppChannel::from(SHARED_MEM::read_from(snapshot.as_slice()).unwrap());
+
+        assert_eq!(
+            c.peek(MsgChannel::Heartbeat),
+            Some(b"<wss>1024</wss>".to_vec())
+        );
+        assert_eq!(
+            c.peek(MsgChannel::AppStatus),
+            Some(b"<fraction_done>0.5</fraction_done>".to_vec())
+        );
+        assert!(c.is_empty(MsgChannel::TrickleUp));
+    }
+
+    #[test]
+    fn test_little_endian_codec() {
+        assert_eq!(encode_u32(0x0102_0304), [4, 3, 2, 1]);
+        assert_eq!(decode_u32([4, 3, 2, 1]), 0x0102_0304);
+
+        let mut mem = SHARED_MEM::default();
+        mem.process_control_request.force_push("<quit/>");
+        let mut snapshot = Vec::new();
+        mem.write_to(&mut snapshot).unwrap();
+
+        // Magic, busy flag, then the length prefix of the first channel.
+        assert_eq!(&snapshot[5..9], &[7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_mmap_transactions() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MmapAppChannel>();
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = MmapAppChannel::new(tmp.path().join("mmapfile")).unwrap();
+
+        assert!(c.is_empty(MsgChannel::Heartbeat));
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, b"<wss>1</wss>".to_vec())) };
+        assert_eq!(
+            c.peek(MsgChannel::Heartbeat),
+            Some(b"<wss>1</wss>".to_vec())
+        );
+        assert_eq!(
+            c.receive(MsgChannel::Heartbeat),
+            Some(b"<wss>1</wss>".to_vec())
+        );
+        assert!(c.is_empty(MsgChannel::Heartbeat));
+    }
+
+    #[test]
+    fn test_mmap_last_handle_unmaps() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        for round in 0..20 {
+            let c = Arc::new(
+                MmapAppChannel::new(tmp.path().join(format!("mmapfile{}", round))).unwrap(),
+            );
+            let threads = (0..4)
+                .map(|_| {
+                    let c = c.clone();
+                    thread::spawn(move || {
+                        for i in 0..100 {
+                            c.force(Message::Control(ControlMessage::Heartbeat(Heartbeat {
+                                wss: Some(i as f64),
+                                max_wss: None,
+                            })));
+                            c.receive(MsgChannel::Heartbeat);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            // Whichever thread finishes last drops the last handle and unmaps the segment.
+            drop(c);
+            for t in threads {
+                t.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_remap_during_transactions() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = MmapAppChannel::new(tmp.path().join("mmapfile")).unwrap();
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut i = 0;
+                    while !done.load(Ordering::SeqCst) {
+                        c.force(Message::Control(ControlMessage::Heartbeat(Heartbeat {
+                            wss: Some(i as f64),
+                            max_wss: None,
+                        })));
+                        c.receive(MsgChannel::Heartbeat);
+                        i += 1;
+                    }
+                });
+            }
+            // Each remap unmaps the mapping the other threads were just using.
+            for _ in 0..50 {
+                c.remap().unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+
+        c.force(Message::Control(ControlMessage::Heartbeat(Heartbeat {
+            wss: Some(1.0),
+            max_wss: None,
+        })));
+        assert!(!c.is_empty(MsgChannel::Heartbeat));
+    }
+
+    #[test]
+    fn test_mmap_drop_after_poisoned_transaction() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = Arc::new(MmapAppChannel::new(tmp.path().join("mmapfile")).unwrap());
+
+        let other = c.clone();
+        let panicked = thread::spawn(move || other.transaction(&|_| panic!("inside transaction")))
+            .join()
+            .is_err();
+        assert!(panicked);
+        assert!(c.ptr.is_poisoned());
+        drop(c);
+    }
+
+    #[test]
+    fn test_split_channel() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let control_path = tmp.path().join("control");
+        let status_path = tmp.path().join("status");
+        let c = SplitAppChannel::new(
+            MmapAppChannel::new(&control_path).unwrap(),
+            MmapAppChannel::new(&status_path).unwrap(),
+        );
+
+        let quit = ControlMessage::ProcessControlRequest(ProcessControlRequest::Quit);
+        let client_control = MmapAppChannel::new(&control_path).unwrap();
+        client_control.force(Message::Control(quit.clone()));
+        assert!(c.is_any_pending(&[MsgChannel::ProcessControlRequest]));
+        assert_eq!(c.pull_control(), Some(quit));
+        assert!(client_control.is_empty(MsgChannel::ProcessControlRequest));
+
+        assert_eq!(c.reply_control(ProcessControlReply::Quit), None);
+        let client_status = MmapAppChannel::new(&status_path).unwrap();
+        assert_eq!(
+            client_status.pull_status(),
+            Some(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit
+            ))
+        );
+        assert!(client_control.is_empty(MsgChannel::ProcessControlReply));
+    }
+
+    #[test]
+    fn test_open_app_channel() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join(MMAP_FILE_NAME);
+
+        let (_, mode) = open_app_channel_at(&path).unwrap();
+        assert_eq!(mode, ChannelMode::Standalone);
+        assert!(!path.exists());
+
+        MmapAppChannel::new(&path).unwrap();
+        let (_, mode) = open_app_channel_at(&path).unwrap();
+        assert_eq!(mode, ChannelMode::Mmap);
+    }
+
+    fn io_error(e: error::Error) -> io::Error {
+        match e {
+            error::Error::Io(e) => e,
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mmap_failure_reported() {
+        let e = match MmapAppChannel::new("/dev/null") {
+            Ok(_) => panic!("mapping /dev/null must fail"),
+            Err(e) => io_error(e),
+        };
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "/dev/null is not a regular file");
+
+        let e = match MmapAppChannel::new("/nonexistent/dir/mmapfile") {
+            Ok(_) => panic!("opening a missing directory must fail"),
+            Err(e) => io_error(e),
+        };
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        let errno = e
+            .get_ref()
+            .and_then(|e| e.source())
+            .and_then(|e| e.downcast_ref::<io::Error>())
+            .and_then(|e| e.raw_os_error());
+        assert_eq!(errno, Some(libc::ENOENT));
+        assert!(e.to_string().starts_with("open failed"), "{}", e);
+
+        // A shared writable mapping of a file opened read-only fails in `mmap` itself.
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("readonly");
+        File::create(&path)
+            .unwrap()
+            .set_len(SHMEM_SIZE as u64)
+            .unwrap();
+        let f = File::open(&path).unwrap();
+
+        let e = match MmapPtr::map_file(&f, 0) {
+            Ok(_) => panic!("a writable mapping of a read-only file must fail"),
+            Err(e) => e,
+        };
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let errno = e
+            .get_ref()
+            .and_then(|e| e.source())
+            .and_then(|e| e.downcast_ref::<io::Error>())
+            .and_then(|e| e.raw_os_error());
+        assert_eq!(errno, Some(libc::EACCES));
+        assert!(e.to_string().starts_with("mmap failed"), "{}", e);
+    }
+
+    #[test]
+    fn test_eintr_retried() {
+        let mut calls = 0;
+        let r = retry_eintr(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from_raw_os_error(libc::EINTR))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(r.unwrap(), 3);
+
+        let mut calls = 0;
+        let r: io::Result<()> = retry_eintr(|| {
+            calls += 1;
+            Err(io::Error::from_raw_os_error(libc::ENOENT))
+        });
+        assert_eq!(r.unwrap_err().raw_os_error(), Some(libc::ENOENT));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_mmap_directory_rejected() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let e = match MmapAppChannel::new(tmp.path()) {
+            Ok(_) => panic!("mapping a directory must fail"),
+            Err(e) => io_error(e),
+        };
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            e.to_string(),
+            format!("{} is not a regular file", tmp.path().display())
+        );
+
+        // Found by `open_app_channel_at`, but still not usable as a channel.
+        assert!(open_app_channel_at(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn test_mmap_segments_at_offsets() {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let stride = (SHMEM_SIZE as u64).div_ceil(page_size) * page_size;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("packed");
+        File::create(&path).unwrap().set_len(2 * stride).unwrap();
+
+        let first = MmapAppChannel::new_at_offset(&path, 0).unwrap();
+        let second = MmapAppChannel::new_at_offset(&path, stride).unwrap();
+        unsafe { first.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+        unsafe { second.force_unchecked((MsgChannel::Heartbeat, "<wss>2</wss>".into())) };
+        assert_eq!(
+            first.receive(MsgChannel::Heartbeat),
+            Some(b"<wss>1</wss>".to_vec())
+        );
+        assert_eq!(
+            second.peek(MsgChannel::Heartbeat),
+            Some(b"<wss>2</wss>".to_vec())
+        );
+        assert!(first.is_empty(MsgChannel::Heartbeat));
+
+        let reopened = MmapAppChannel::new_at_offset(&path, stride).unwrap();
+        assert_eq!(
+            reopened.receive(MsgChannel::Heartbeat),
+            Some(b"<wss>2</wss>".to_vec())
+        );
+        assert!(second.is_empty(MsgChannel::Heartbeat));
+
+        for offset in [1, 2 * stride] {
+            match MmapAppChannel::new_at_offset(&path, offset) {
+                Ok(_) => panic!("offset {} must be rejected", offset),
+                Err(e) => assert_eq!(io_error(e).kind(), io::ErrorKind::InvalidInput),
+            }
+        }
+    }
+
+    #[test]
+    fn test_named_segment_unlink() {
+        let name = format!("/boinc-app-api-test-{}", std::process::id());
+
+        let creator = MmapAppChannel::open_named(&name, true).unwrap();
+        unsafe { creator.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+
+        let reader = MmapAppChannel::open_named(&name, false).unwrap();
+        assert_eq!(
+            reader.peek(MsgChannel::Heartbeat),
+            Some(b"<wss>1</wss>".to_vec())
+        );
+        drop(reader);
+
+        // Readers do not unlink.
+        let reader = MmapAppChannel::open_named(&name, false).unwrap();
+        drop(reader);
+
+        drop(creator);
+        let e = match MmapAppChannel::open_named(&name, false) {
+            Ok(_) => panic!("segment must be unlinked by its creator"),
+            Err(e) => io_error(e),
+        };
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_named_segment_leak() {
+        let name = format!("/boinc-app-api-leak-test-{}", std::process::id());
+
+        let creator = MmapAppChannel::open_named(&name, true).unwrap();
+        creator.leak();
+        drop(creator);
+
+        let owner = MmapAppChannel::open_named(&name, true).unwrap();
+        drop(owner);
+        assert!(MmapAppChannel::open_named(&name, false).is_err());
+    }
+
+    #[test]
+    fn test_mmap_advise() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = MmapAppChannel::new(tmp.path().join(MMAP_FILE_NAME)).unwrap();
+
+        c.advise(MmapAdvice::Random).unwrap();
+        c.advise(MmapAdvice::WillNeed).unwrap();
+        c.force(Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        )));
+        assert!(!c.is_empty(MsgChannel::ProcessControlReply));
+    }
+
+    #[test]
+    fn test_try_transaction_timeout() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = MmapAppChannel::new(tmp.path().join(MMAP_FILE_NAME)).unwrap();
+
+        let (locked_tx, locked_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        thread::scope(|s| {
+            let c = &c;
+            s.spawn(move || {
+                c.transaction(&|_| {
+                    locked_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                })
+            });
+            locked_rx.recv().unwrap();
+
+            let e = c
+                .try_transaction(Duration::from_millis(50), &|_| {
+                    panic!("lock must not be acquired")
+                })
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+
+            release_tx.send(()).unwrap();
+        });
+
+        c.try_transaction(Duration::from_millis(50), &|data| {
+            data.heartbeat.force_push("<wss>1</wss>")
+        })
+        .unwrap();
+        assert!(!c.is_empty(MsgChannel::Heartbeat));
+    }
+
+    #[test]
+    fn test_try_transaction_poisoned() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = MmapAppChannel::new(tmp.path().join(MMAP_FILE_NAME)).unwrap();
+
+        thread::scope(|s| {
+            let c = &c;
+            let r = s
+                .spawn(move || c.transaction(&|_| panic!("poison the lock")))
+                .join();
+            assert!(r.is_err());
+        });
+
+        let e = c
+            .try_transaction(Duration::from_millis(50), &|_| {
+                panic!("poisoned lock must not be entered")
+            })
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Other);
+        assert!(e.to_string().contains("poisoned"), "{}", e);
+    }
+
+    #[test]
+    fn test_fresh_mapping_is_empty() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let c = MmapAppChannel::new(tmp.path().join(MMAP_FILE_NAME)).unwrap();
+        assert!(c.with_transaction(|data| data.is_empty()));
+
+        c.force(Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        )));
+        assert!(!c.with_transaction(|data| data.is_empty()));
+        assert!(SHARED_MEM::zeroed().is_empty());
+    }
+
+    #[test]
+    fn test_mmap_remap() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("mmapfile");
+        let replacement = tmp.path().join("mmapfile.new");
+
+        let c = MmapAppChannel::new(&path).unwrap();
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "old".into())) };
+
+        {
+            let other = MmapAppChannel::new(&replacement).unwrap();
+            unsafe { other.force_unchecked((MsgChannel::Heartbeat, "new".into())) };
+        }
+        std::fs::rename(&replacement, &path).unwrap();
+        assert_eq!(c.peek(MsgChannel::Heartbeat), Some(b"old".to_vec()));
+
+        c.remap().unwrap();
+        assert_eq!(c.peek(MsgChannel::Heartbeat), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_peek_text_truncated_utf8() {
+        let c = MemoryAppChannel::default();
+
+        unsafe { c.force_unchecked((MsgChannel::GraphicsReply, "<url>caf".into())) };
+        assert_eq!(
+            c.peek_text(MsgChannel::GraphicsReply),
+            Some(PayloadText {
+                text: "<url>caf".into(),
+                valid: true,
+            })
+        );
+
+        unsafe { c.force_unchecked((MsgChannel::GraphicsReply, b"<url>caf\xc3".to_vec())) };
+        assert_eq!(
+            c.peek_text(MsgChannel::GraphicsReply),
+            Some(PayloadText {
+                text: "<url>caf\u{fffd}".into(),
+                valid: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_is_any_pending() {
+        let c = MemoryAppChannel::default();
+        let all = MsgChannel::enum_iter().collect::<Vec<_>>();
+
+        assert!(!c.is_any_pending(&all));
+
+        unsafe { c.force_unchecked((MsgChannel::TrickleDown, "<have_new_trickle_down/>".into())) };
+        assert!(c.is_any_pending(&all));
+        assert!(!c.is_any_pending(&[MsgChannel::Heartbeat, MsgChannel::AppStatus]));
+    }
+
+    #[test]
+    fn test_receive_until_deadline() {
+        let c = MemoryAppChannel::default();
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+        assert_eq!(
+            c.receive_until(MsgChannel::Heartbeat, deadline, Duration::from_millis(10)),
+            None
+        );
+        assert!(Instant::now() >= deadline);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+        assert_eq!(
+            c.receive_timeout(
+                MsgChannel::Heartbeat,
+                Duration::from_secs(5),
+                Duration::from_millis(10)
+            ),
+            Some(b"<wss>1</wss>".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_zero_poll_interval_yields() {
+        struct CountingChannel(MemoryAppChannel, std::sync::atomic::AtomicUsize);
+
+        impl AppChannel for CountingChannel {
+            fn transaction(&self, f: &dyn Fn(&mut SHARED_MEM)) {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.transaction(f)
+            }
+        }
+
+        let c = CountingChannel(MemoryAppChannel::default(), Default::default());
+        let window = Duration::from_millis(50);
+        assert_eq!(
+            c.receive_timeout(MsgChannel::Heartbeat, window, Duration::ZERO),
+            None
+        );
+
+        // One poll per `MIN_POLL_INTERVAL` at most, where a busy loop would make many thousands.
+        let polls = c.1.load(Ordering::SeqCst);
+        let max_polls = (window.as_millis() / MIN_POLL_INTERVAL.as_millis()) as usize + 2;
+        assert!(polls <= max_polls, "{} polls", polls);
+    }
+
+    #[test]
+    fn test_reply_control() {
+        let c = MemoryAppChannel::default();
+
+        assert_eq!(c.reply_control(ProcessControlReply::Quit), None);
+        assert_eq!(
+            c.reply_control(ProcessControlReply::Suspend),
+            Some(ProcessControlReply::Suspend)
+        );
+        assert_eq!(
+            c.pull_status(),
+            Some(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit
+            ))
+        );
+    }
+
+    #[test]
+    fn test_with_transaction() {
+        let c: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        unsafe { c.force_unchecked((MsgChannel::TrickleDown, "<have_new_trickle_down/>".into())) };
+
+        let moved = c.with_transaction(|data| {
+            let v = data.trickle_down.pop()?;
+            data.trickle_up.force_push(v.clone());
+            Some(v)
+        });
+
+        assert_eq!(moved, Some(b"<have_new_trickle_down/>".to_vec()));
+        assert!(c.is_empty(MsgChannel::TrickleDown));
+        assert_eq!(
+            c.peek(MsgChannel::TrickleUp),
+            Some(b"<have_new_trickle_down/>".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_observe() {
+        let c = MemoryAppChannel::default();
+        c.force(Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        )));
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+
+        let occupied = c.observe(|data| {
+            MsgChannel::enum_iter()
+                .filter(|c| !data.get_channel(*c).is_empty())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(
+            occupied,
+            vec![MsgChannel::ProcessControlReply, MsgChannel::Heartbeat]
+        );
+    }
+
+    #[test]
+    fn test_peek_keeps_message() {
+        let c = MemoryAppChannel::default();
+
+        let control = ControlMessage::ProcessControlRequest(ProcessControlRequest::Suspend {
+            reason: SuspendReason::empty(),
+        });
+        c.force(Message::Control(control.clone()));
+        assert_eq!(c.peek_control(), Some(control.clone()));
+        assert_eq!(c.pull_control(), Some(control));
+        assert_eq!(c.peek_control(), None);
+
+        let status = StatusMessage::TrickleUp(TrickleUpData {
+            have_new_trickle_up: true,
+            have_new_upload_file: false,
+        });
+        c.force(Message::Status(status.clone()));
+        assert_eq!(c.peek_status(), Some(status.clone()));
+        assert_eq!(c.pull_status(), Some(status));
+        assert_eq!(c.peek_status(), None);
+    }
+
+    #[test]
+    fn test_receive_all_status() {
+        let c = MemoryAppChannel::default();
+
+        let reply = StatusMessage::ProcessControlReply(ProcessControlReply::Quit);
+        let trickle = StatusMessage::TrickleUp(TrickleUpData {
+            have_new_trickle_up: true,
+            have_new_upload_file: false,
+        });
+        c.force(Message::Status(reply.clone()));
+        c.force(Message::Status(trickle.clone()));
+        unsafe { c.force_unchecked((MsgChannel::AppStatus, "<current_cpu_time>".into())) };
+
+        let mut undecodable = Vec::new();
+        assert_eq!(c.receive_all_status(&mut undecodable), vec![reply, trickle]);
+        assert_eq!(
+            undecodable,
+            vec![(StatusMsgChannel::AppStatus, b"<current_cpu_time>".to_vec())]
+        );
+        for id in StatusMsgChannel::enum_iter() {
+            assert!(c.is_empty(id.into()));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_stats_json() {
+        let c = MemoryAppChannel::default();
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+
+        let json = c.stats_json();
+        for id in MsgChannel::enum_iter() {
+            assert!(json.contains(&format!("\"{:?}\":", id)), "{}", json);
+        }
+        assert!(
+            json.contains(r#""Heartbeat":{"len":12,"occupied":true}"#),
+            "{}",
+            json
+        );
+    }
+
+    #[test]
+    fn test_free_space() {
+        let c = MemoryAppChannel::default();
+        assert_eq!(
+            c.free_space(MsgChannel::TrickleUp),
+            MSG_CHANNEL::MAX_PAYLOAD
+        );
+
+        unsafe { c.force_unchecked((MsgChannel::TrickleUp, "<have_new_trickle_up/>".into())) };
+        assert_eq!(c.free_space(MsgChannel::TrickleUp), 0);
+        assert_eq!(
+            c.free_space(MsgChannel::TrickleDown),
+            MSG_CHANNEL::MAX_PAYLOAD
+        );
+
+        c.clear(MsgChannel::TrickleUp);
+        assert_eq!(
+            c.free_space(MsgChannel::TrickleUp),
+            MSG_CHANNEL::MAX_PAYLOAD
+        );
+    }
+
+    #[test]
+    fn test_forward() {
+        let c = MemoryAppChannel::default();
+        assert!(!c.forward(MsgChannel::TrickleDown, MsgChannel::TrickleUp));
+
+        unsafe { c.force_unchecked((MsgChannel::TrickleDown, "<have_new_trickle_down/>".into())) };
+        assert!(c.forward(MsgChannel::TrickleDown, MsgChannel::TrickleUp));
+        assert!(c.is_empty(MsgChannel::TrickleDown));
+        assert_eq!(
+            c.peek(MsgChannel::TrickleUp),
+            Some(b"<have_new_trickle_down/>".to_vec())
+        );
+
+        unsafe { c.force_unchecked((MsgChannel::TrickleDown, "<upload_file_status/>".into())) };
+        assert!(!c.forward(MsgChannel::TrickleDown, MsgChannel::TrickleUp));
+        assert_eq!(
+            c.peek(MsgChannel::TrickleDown),
+            Some(b"<upload_file_status/>".to_vec())
+        );
+        assert_eq!(
+            c.peek(MsgChannel::TrickleUp),
+            Some(b"<have_new_trickle_down/>".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_pull_skips_out_of_range_values() {
+        let c = MemoryAppChannel::default();
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>-1</wss>".into())) };
+        unsafe {
+            c.force_unchecked((
+                MsgChannel::AppStatus,
+                "<fraction_done>1.0000001</fraction_done>".into(),
+            ))
+        };
+        assert_eq!(c.pull_control(), None);
+        assert_eq!(c.pull_status(), None);
+        assert!(c.is_empty(MsgChannel::Heartbeat));
+        assert!(c.is_empty(MsgChannel::AppStatus));
+
+        // A valid message behind an undecodable one is still delivered.
+        unsafe { c.force_unchecked((MsgChannel::AppStatus, "<other_pid>0</other_pid>".into())) };
+        c.force(Message::Status(StatusMessage::TrickleUp(TrickleUpData {
+            have_new_trickle_up: true,
+            have_new_upload_file: false,
+        })));
+        assert_eq!(
+            c.pull_status(),
+            Some(StatusMessage::TrickleUp(TrickleUpData {
+                have_new_trickle_up: true,
+                have_new_upload_file: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_pull_control_ignores_unknown_tag() {
+        let c = MemoryAppChannel::default();
+        unsafe { c.force_unchecked((MsgChannel::ProcessControlRequest, "<frobnicate/>".into())) };
+        c.force(Message::Control(ControlMessage::GraphicsRequest));
+        assert_eq!(c.pull_control(), Some(ControlMessage::GraphicsRequest));
+        assert!(c.is_empty(MsgChannel::ProcessControlRequest));
+
+        unsafe { c.force_unchecked((MsgChannel::ProcessControlRequest, "<frobnicate/>".into())) };
+        assert_eq!(c.pull_control(), None);
+        assert!(c.is_empty(MsgChannel::ProcessControlRequest));
+
+        unsafe { c.force_unchecked((MsgChannel::ProcessControlRequest, "<frobnicate/>".into())) };
+        c.force(Message::Control(ControlMessage::GraphicsRequest));
+        let priority = PollPriority::new(&[ControlMsgChannel::GraphicsRequest]);
+        assert_eq!(
+            c.pull_control_by_with(&priority, UnknownControlPolicy::Passthrough)
+                .unwrap(),
+            Some(ControlMessage::GraphicsRequest)
+        );
+        assert_eq!(
+            c.pull_control_by_with(&priority, UnknownControlPolicy::Passthrough)
+                .unwrap(),
+            Some(ControlMessage::Unknown(b"<frobnicate/>".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_len_of() {
+        let c = MemoryAppChannel::default();
+        assert_eq!(c.len_of(MsgChannel::Heartbeat), 0);
+
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1024</wss>".into())) };
+        assert_eq!(c.len_of(MsgChannel::Heartbeat), 15);
+        assert_eq!(c.len_of(MsgChannel::AppStatus), 0);
+
+        c.clear(MsgChannel::Heartbeat);
+        assert_eq!(c.len_of(MsgChannel::Heartbeat), 0);
+    }
+
+    #[test]
+    fn test_map_transaction_reads_two_channels() {
+        let c: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        c.force(Message::Control(ControlMessage::Heartbeat(Heartbeat {
+            wss: Some(1.0),
+            max_wss: None,
+        })));
+        c.force(Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        )));
+
+        let (heartbeat, reply) = c.map_transaction(
+            |data| (data.heartbeat.pop(), data.process_control_reply.pop()),
+            |(h, r)| {
+                (
+                    h.map(|v| ControlMessage::from_raw(ControlMsgChannel::Heartbeat, v).unwrap()),
+                    r.map(|v| {
+                        StatusMessage::from_raw(StatusMsgChannel::ProcessControlReply, v).unwrap()
+                    }),
+                )
+            },
+        );
+        assert_eq!(
+            heartbeat,
+            Some(ControlMessage::Heartbeat(Heartbeat {
+                wss: Some(1.0),
+                max_wss: None,
+            }))
+        );
+        assert_eq!(
+            reply,
+            Some(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit
+            ))
+        );
+        assert!(c.is_empty(MsgChannel::Heartbeat));
+        assert!(c.is_empty(MsgChannel::ProcessControlReply));
+    }
+
+    #[test]
+    fn test_unknown_control_policy() {
+        let c = MemoryAppChannel::default();
+        let unknown = || unsafe {
+            c.force_unchecked((MsgChannel::ProcessControlRequest, "<reboot/>".into()));
+        };
+        let heartbeat = ControlMessage::Heartbeat(Heartbeat {
+            wss: None,
+            max_wss: None,
+        });
+
+        unknown();
+        c.force(Message::Control(heartbeat.clone()));
+        assert_eq!(
+            c.pull_control_with(UnknownControlPolicy::Ignore).unwrap(),
+            Some(heartbeat)
+        );
+        assert!(c.is_empty(MsgChannel::ProcessControlRequest));
+
+        unknown();
+        assert!(c.pull_control_with(UnknownControlPolicy::Error).is_err());
+        assert!(c.is_empty(MsgChannel::ProcessControlRequest));
+
+        unknown();
+        assert_eq!(
+            c.pull_control_with(UnknownControlPolicy::Passthrough)
+                .unwrap(),
+            Some(ControlMessage::Unknown(b"<reboot/>".to_vec()))
+        );
+        assert_eq!(
+            c.pull_control_with(UnknownControlPolicy::Passthrough)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_trickle_up_confirmed() {
+        let c: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let trickle = TrickleUpData {
+            have_new_trickle_up: true,
+            have_new_upload_file: false,
+        };
+
+        let client = c.clone();
+        let consumer = thread::spawn(move || {
+            client
+                .receive_timeout(
+                    MsgChannel::TrickleUp,
+                    Duration::from_secs(10),
+                    Duration::from_millis(1),
+                )
+                .unwrap()
+        });
+        assert_eq!(
+            c.send_trickle_up_confirmed(&trickle, Duration::from_secs(10)),
+            Ok(())
+        );
+        assert_eq!(consumer.join().unwrap(), trickle.to_xml());
+        assert!(c.is_empty(MsgChannel::TrickleUp));
+
+        // Nobody consumes this one, so it stays pending.
+        assert_eq!(
+            c.send_trickle_up_confirmed(&trickle, Duration::from_millis(20)),
+            Err(TrickleTimeout)
+        );
+        assert!(!c.is_empty(MsgChannel::TrickleUp));
+    }
+
+    #[test]
+    fn test_clear_if() {
+        let c = MemoryAppChannel::default();
+        let is_heartbeat = |v: &[u8]| v.starts_with(b"<wss>");
+        assert!(!c.clear_if(MsgChannel::Heartbeat, |_| true));
+
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<max_wss>1</max_wss>".into())) };
+        assert!(!c.clear_if(MsgChannel::Heartbeat, is_heartbeat));
+        assert!(!c.is_empty(MsgChannel::Heartbeat));
+
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+        assert!(c.clear_if(MsgChannel::Heartbeat, is_heartbeat));
+        assert!(c.is_empty(MsgChannel::Heartbeat));
+    }
+
+    #[test]
+    fn test_handle_control() {
+        let c = MemoryAppChannel::default();
+        assert!(!c.handle_control(|_| panic!("no request is pending")));
+
+        c.force(Message::Control(ControlMessage::ProcessControlRequest(
+            ProcessControlRequest::Quit,
+        )));
+        assert!(c.handle_control(|request| {
+            assert_eq!(
+                request,
+                ControlMessage::ProcessControlRequest(ProcessControlRequest::Quit)
+            );
+            ProcessControlReply::Quit
+        }));
+
+        assert!(c.is_empty(MsgChannel::ProcessControlRequest));
+        assert_eq!(
+            c.pull_status(),
+            Some(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit
+            ))
+        );
+    }
+
+    #[test]
+    fn test_force_if_empty() {
+        let c = MemoryAppChannel::default();
+        let quit = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        ));
+        let suspend = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Suspend,
+        ));
+
+        assert!(c.force_if_empty(quit));
+        assert!(!c.force_if_empty(suspend));
+        assert_eq!(
+            c.pull_status(),
+            Some(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit
+            ))
+        );
+    }
+
+    #[test]
+    fn test_high_bytes_round_trip() {
+        for b in 0..=255u8 {
+            assert_eq!(from_c_char(to_c_char(b)), b);
+        }
+
+        // NUL terminates the payload, every other byte value must come back unchanged.
+        let payload: Vec<u8> = (1..=255u8).collect();
+        let mut ch = MSG_CHANNEL::default();
+        ch.force_push(payload.clone());
+        assert_eq!(ch.peek(), Some(payload.clone()));
+
+        let mut mem = SHARED_MEM::default();
+        mem.trickle_up.force_push(payload.clone());
+        let mut snapshot = Vec::new();
+        mem.write_to(&mut snapshot).unwrap();
+        let restored = SHARED_MEM::read_from(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.trickle_up.peek(), Some(payload));
+    }
+
+    #[test]
+    fn test_channel_validate() {
+        let mut ch = MSG_CHANNEL::default();
+        assert_eq!(ch.validate(), Ok(()));
+        ch.force_push("<quit/>");
+        assert_eq!(ch.validate(), Ok(()));
+        ch.force_push(vec![b'x'; MSG_CHANNEL::MAX_PAYLOAD]);
+        assert_eq!(ch.validate(), Ok(()));
+
+        // Leftovers of a consumed message are not looked at.
+        ch.buf = [to_c_char(b'x'); MSG_CHANNEL_SIZE];
+        ch.clear();
+        assert_eq!(ch.validate(), Ok(()));
+
+        ch.set_flag(1);
+        assert_eq!(
+            ch.validate(),
+            Err("occupied channel has no terminator within the buffer")
+        );
+
+        let mut ch = MSG_CHANNEL::default();
+        ch.set_flag(1);
+        assert_eq!(ch.validate(), Err("occupied channel has an empty payload"));
+
+        ch.set_flag(2);
+        assert_eq!(ch.validate(), Err("busy flag is neither 0 nor 1"));
+    }
+
+    #[test]
+    fn test_health_check() {
+        let c = MemoryAppChannel::default();
+        c.force(Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Quit,
+        )));
+        c.force(Message::Control(ControlMessage::GraphicsRequest));
+        assert!(c.health_check().is_healthy());
+
+        let mut mem = SHARED_MEM::default();
+        mem.heartbeat.buf[0] = to_c_char(7);
+        mem.trickle_up.buf = [to_c_char(1); MSG_CHANNEL_SIZE];
+        let c = MemoryAppChannel::from(mem);
+        assert_eq!(
+            c.health_check(),
+            HealthReport {
+                readable: true,
+                corrupt_channels: vec![MsgChannel::Heartbeat, MsgChannel::TrickleUp],
+            }
+        );
+
+        let c = Arc::new(MemoryAppChannel::default());
+        let other = c.clone();
+        assert!(
+            thread::spawn(move || other.transaction(&|_| panic!("inside transaction")))
+                .join()
+                .is_err()
+        );
+        let report = c.health_check();
+        assert!(!report.readable);
+        assert!(!report.is_healthy());
+        assert_eq!(c.checked_transaction(&|_| {}), Err(Poisoned));
+
+        let c = ShardedAppChannel::default();
+        assert!(thread::scope(|s| {
+            s.spawn(|| c.transaction(&|_| panic!("inside transaction")))
+                .join()
+                .is_err()
+        }));
+        assert!(!c.health_check().readable);
+    }
+
+    #[test]
+    fn test_validate_garbage() {
+        let mut mem = SHARED_MEM::default();
+        assert_eq!(mem.validate(), Ok(()));
+
+        for c in MsgChannel::enum_iter() {
+            mem.get_channel_mut(c).buf = [to_c_char(0xA5); MSG_CHANNEL_SIZE];
+        }
+        let c = MemoryAppChannel::from(mem);
+        assert_eq!(c.validate(), Err(Corrupted));
+    }
+
+    #[test]
+    fn test_sharded_channel() {
+        let c = ShardedAppChannel::default();
+        let reply = StatusMessage::ProcessControlReply(ProcessControlReply::Quit);
+        c.force(Message::Status(reply.clone()));
+        assert_eq!(c.peek_status(), Some(reply));
+        assert!(!c.is_empty(MsgChannel::ProcessControlReply));
+        assert!(c.is_empty(MsgChannel::ProcessControlRequest));
+
+        // A held channel does not block the others.
+        let (locked_tx, locked_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        thread::scope(|s| {
+            let c = &c;
+            s.spawn(move || {
+                c.channel_transaction(MsgChannel::Heartbeat, &|_| {
+                    locked_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                })
+            });
+            locked_rx.recv().unwrap();
+            let received = c.receive(MsgChannel::ProcessControlReply);
+            release_tx.send(()).unwrap();
+            assert_eq!(received, Some(b"<quit/>".to_vec()));
+        });
+    }
+
+    #[test]
+    fn test_condvar_wakes_receiver() {
+        let c = CondvarAppChannel::default();
+        let (started_tx, started_rx) = channel();
+        thread::scope(|s| {
+            let c = &c;
+            let receiver = s.spawn(move || {
+                started_tx.send(()).unwrap();
+                let v = c.recv_blocking(MsgChannel::ProcessControlReply);
+                (v, Instant::now())
+            });
+            started_rx.recv().unwrap();
+            thread::sleep(Duration::from_millis(50));
+
+            let pushed = Instant::now();
+            c.force(Message::Status(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit,
+            )));
+            let (v, woken) = receiver.join().unwrap();
+            assert_eq!(v, b"<quit/>".to_vec());
+            assert!(woken.duration_since(pushed) < Duration::from_millis(500));
+        });
+        assert!(c.is_empty(MsgChannel::ProcessControlReply));
+    }
+
+    #[test]
+    fn test_condvar_receive_timeout() {
+        let c = CondvarAppChannel::default();
+        let started = Instant::now();
+        assert_eq!(
+            c.recv_timeout(MsgChannel::Heartbeat, Duration::from_millis(50)),
+            None
+        );
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        thread::scope(|s| {
+            let c = &c;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+            });
+            let started = Instant::now();
+            assert_eq!(
+                c.receive_timeout(
+                    MsgChannel::Heartbeat,
+                    Duration::from_secs(10),
+                    Duration::from_secs(10)
+                ),
+                Some(b"<wss>1</wss>".to_vec())
+            );
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    fn test_delayed_channel() {
+        let delay = Duration::from_millis(20);
+        let c = DelayedAppChannel::new(Arc::new(MemoryAppChannel::default()), delay);
+
+        let started = Instant::now();
+        unsafe { c.force_unchecked((MsgChannel::Heartbeat, "<wss>1</wss>".into())) };
+        assert!(started.elapsed() >= delay);
+
+        let started = Instant::now();
+        assert_eq!(
+            c.peek_control(),
+            Some(ControlMessage::Heartbeat(Heartbeat {
+                wss: Some(1.0),
+                max_wss: None,
+            }))
+        );
+        assert!(started.elapsed() >= delay);
+
+        let started = Instant::now();
+        assert_eq!(
+            c.receive(MsgChannel::Heartbeat),
+            Some(b"<wss>1</wss>".to_vec())
+        );
+        assert!(started.elapsed() >= delay);
+    }
+
+    #[test]
+    fn test_push_retry() {
+        let c = MemoryAppChannel::default();
+        let first = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Suspend,
+        ));
+        let second = Message::Status(StatusMessage::ProcessControlReply(
+            ProcessControlReply::Resume,
+        ));
+        c.force(first);
+
+        assert_eq!(
+            c.push_retry(second.clone(), 2, Duration::from_millis(1)),
+            Err(second.clone())
+        );
+
+        thread::scope(|s| {
+            let c = &c;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                c.receive(MsgChannel::ProcessControlReply)
+            });
+            assert_eq!(
+                c.push_retry(second.clone(), 3, Duration::from_millis(100)),
+                Ok(())
+            );
+        });
+        assert_eq!(
+            c.pull_status(),
+            Some(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Resume
+            ))
+        );
+    }
+
+    #[test]
+    fn test_drain_typed() {
+        let c = MemoryAppChannel::default();
+        let messages = vec![
+            Message::Control(ControlMessage::ProcessControlRequest(
+                ProcessControlRequest::Suspend {
+                    reason: SuspendReason::empty(),
+                },
+            )),
+            Message::Status(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Suspend,
+            )),
+            Message::Status(StatusMessage::TrickleUp(TrickleUpData {
+                have_new_trickle_up: true,
+                have_new_upload_file: false,
+            })),
+            Message::Control(ControlMessage::TrickleDown(TrickleDownData {
+                have_new_trickle_down: true,
+                upload_file_status: false,
+            })),
+        ];
+        for m in &messages {
+            c.force(m.clone());
+        }
+        unsafe { c.force_unchecked((MsgChannel::AppStatus, "<fraction_done>".into())) };
+
+        let mut undecodable = Vec::new();
+        assert_eq!(c.drain_typed(&mut undecodable), messages);
+        assert_eq!(
+            undecodable,
+            vec![(MsgChannel::AppStatus, b"<fraction_done>".to_vec())]
+        );
+        assert!(c.with_transaction(|data| data.is_empty()));
+    }
+
+    #[test]
+    fn test_poll_control_batched() {
+        let c = MemoryAppChannel::default();
+        let messages = vec![
+            ControlMessage::ProcessControlRequest(ProcessControlRequest::Suspend {
+                reason: SuspendReason::empty(),
+            }),
+            ControlMessage::GraphicsRequest,
+            ControlMessage::Heartbeat(Heartbeat {
+                wss: Some(1.0),
+                max_wss: None,
+            }),
+            ControlMessage::TrickleDown(TrickleDownData {
+                have_new_trickle_down: true,
+                upload_file_status: false,
+            }),
+        ];
+        for m in &messages {
+            c.force(Message::Control(m.clone()));
+        }
+
+        assert_eq!(c.poll_control_batched(3), messages[..3].to_vec());
+        assert_eq!(c.poll_control_batched(3), messages[3..].to_vec());
+        assert_eq!(c.poll_control_batched(3), vec![]);
+
+        unsafe { c.force_unchecked((MsgChannel::ProcessControlRequest, "<frobnicate/>".into())) };
+        c.force(Message::Control(messages[1].clone()));
+        assert_eq!(c.poll_control_batched(1), messages[1..2].to_vec());
+        assert_eq!(
+            c.peek(MsgChannel::ProcessControlRequest),
+            Some(b"<frobnicate/>".to_vec())
+        );
+        assert_eq!(
+            c.pull_control_with(UnknownControlPolicy::Passthrough)
+                .unwrap(),
+            Some(ControlMessage::Unknown(b"<frobnicate/>".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_poll_priority_graphics_first() {
+        let priority = PollPriority::new(&[ControlMsgChannel::GraphicsRequest]);
+        assert_eq!(
+            priority.channels(),
+            &[
+                ControlMsgChannel::GraphicsRequest,
+                ControlMsgChannel::ProcessControlRequest,
+                ControlMsgChannel::Heartbeat,
+                ControlMsgChannel::TrickleDown,
+            ]
+        );
+
+        let c = MemoryAppChannel::default();
+        let quit = ControlMessage::ProcessControlRequest(ProcessControlRequest::Quit);
+        let messages = [quit.clone(), ControlMessage::GraphicsRequest];
+        for m in &messages {
+            c.force(Message::Control(m.clone()));
+        }
+        assert_eq!(
+            c.poll_control_batched_by(2, &priority),
+            vec![ControlMessage::GraphicsRequest, quit.clone()]
+        );
+
+        for m in &messages {
+            c.force(Message::Control(m.clone()));
+        }
+        assert_eq!(
+            c.pull_control_by(&priority),
+            Some(ControlMessage::GraphicsRequest)
+        );
+        assert_eq!(c.pull_control_by(&priority), Some(quit.clone()));
+
+        for m in &messages {
+            c.force(Message::Control(m.clone()));
+        }
+        assert_eq!(c.pull_control(), Some(quit));
+    }
+
+    #[test]
+    fn test_report_fraction_done() {
+        let c = MemoryAppChannel::default();
+
+        assert!(c.report_fraction_done(1.5));
+        assert!(!c.report_fraction_done(0.5));
+        assert_eq!(
+            c.peek(MsgChannel::AppStatus),
+            Some(b"<fraction_done>1</fraction_done>\n".to_vec())
+        );
+
+        c.clear(MsgChannel::AppStatus);
+        assert!(c.report_fraction_done(0.25));
+        match c.pull_status() {
+            Some(StatusMessage::AppStatus(v)) => {
+                assert_eq!(v.fraction_done, 0.25);
+                assert_eq!(v.current_cpu_time, 0.0);
+                assert_eq!(v.checkpoint_cpu_time, 0.0);
+            }
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_events() {
+        use tracing::{field, span, Event, Metadata, Subscriber};
+
+        struct Capture(Arc<Mutex<Vec<String>>>);
+
+        struct MessageVisitor(String);
+
+        impl field::Visit for MessageVisitor {
+            fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0 = format!("{:?}", value);
+                }
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut v = MessageVisitor(String::new());
+                event.record(&mut v);
+                self.0.lock().unwrap().push(v.0);
+            }
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Capture(events.clone()), || {
+            let c = MemoryAppChannel::default();
+            let m = Message::Status(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit,
+            ));
+            assert_eq!(c.push(m.clone()), None);
+            assert_eq!(c.push(m.clone()), Some(m));
+            assert!(c.receive(MsgChannel::ProcessControlReply).is_some());
+
+            let tmp = tempfile::TempDir::new().unwrap();
+            let (_, mode) = open_app_channel_at(tmp.path().join(MMAP_FILE_NAME)).unwrap();
+            assert_eq!(mode, ChannelMode::Standalone);
+        });
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "push accepted",
+                "push rejected",
+                "received",
+                "selected channel mode"
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+/// Approximates a CPU limit by alternating between running and idling within a fixed period of its own. The
+/// duty cycle is local to the application and not derived from the client's suspend and resume requests.
+pub struct Throttler {
+    fraction: f64,
+    period: Duration,
+    start: Instant,
+}
+
+impl Throttler {
+    /// Run during `fraction` of every `period`. A fraction of 1.0 or more, a NaN fraction or a zero period disables
+    /// throttling, a fraction of 0.0 or less never runs.
+    pub fn new(fraction: f64, period: Duration) -> Self {
+        let fraction = if fraction.is_nan() || period.is_zero() {
+            1.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        Self {
+            fraction,
+            period,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn fraction(&self) -> f64 {
+        self.fraction
+    }
+
+    /// Check if the compute loop should be running at this moment.
+    pub fn should_run_now(&self) -> bool {
+        self.should_run_at(Instant::now())
+    }
+
+    pub fn should_run_at(&self, now: Instant) -> bool {
+        if self.fraction >= 1.0 {
+            return true;
+        }
+        if self.fraction <= 0.0 {
+            return false;
+        }
+        let period = self.period.as_secs_f64();
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f64();
+        elapsed % period < period * self.fraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duty_cycle() {
+        let throttler = Throttler::new(0.3, Duration::from_secs(1));
+        let step = Duration::from_millis(1);
+
+        let samples = 10_000;
+        let running = (0..samples)
+            .filter(|i| throttler.should_run_at(throttler.start + step * *i))
+            .count();
+
+        let ratio = running as f64 / samples as f64;
+        assert!((ratio - 0.3).abs() < 0.01, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn test_unthrottled() {
+        let throttler = Throttler::new(1.0, Duration::from_secs(1));
+        assert!(throttler.should_run_at(throttler.start + Duration::from_millis(999)));
+    }
+
+    #[test]
+    fn test_zero_period_unthrottled() {
+        let throttler = Throttler::new(0.3, Duration::ZERO);
+        assert_eq!(throttler.fraction(), 1.0);
+        assert!(throttler.should_run_at(throttler.start));
+        assert!(throttler.should_run_at(throttler.start + Duration::from_millis(999)));
+
+        let throttler = Throttler::new(f64::NAN, Duration::from_secs(1));
+        assert!(throttler.should_run_at(throttler.start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_zero_fraction_never_runs() {
+        let throttler = Throttler::new(0.0, Duration::from_secs(1));
+        assert!(
+            (0..1000).all(|i| !throttler.should_run_at(throttler.start + Duration::from_millis(i)))
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Framing for trickle payloads larger than a single channel slot.
+//!
+//! A payload is split into parts of the form `<part seq="1" total="3">...</part>` which are written one after
+//! another, each once the previous one has been consumed, and put back together by a `Reassembler`.
+
+use crate::{
+    models::{MalformedMessage, MsgChannel},
+    shmem::*,
+};
+use std::{
+    cmp::max,
+    thread,
+    time::{Duration, Instant},
+};
+
+const PART_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn part_overhead(total: usize) -> usize {
+    format!("<part seq=\"{0}\" total=\"{0}\"></part>", total).len()
+}
+
+/// The payload contains a NUL byte, which would end the channel contents early.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NulInPayload {
+    pub position: usize,
+}
+
+impl std::fmt::Display for NulInPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "payload contains a NUL byte at {}", self.position)
+    }
+}
+
+impl std::error::Error for NulInPayload {}
+
+/// Split `payload` into framed parts that each fit into `MSG_CHANNEL::MAX_PAYLOAD`. An empty payload is sent as
+/// a single empty part. Fails if the payload contains a NUL byte, which terminates channel contents.
+pub fn split_parts(payload: &[u8]) -> Result<Vec<Vec<u8>>, NulInPayload> {
+    if let Some(position) = payload.iter().position(|b| *b == 0) {
+        return Err(NulInPayload { position });
+    }
+
+    // The frame grows with the number of digits of `total`, which in turn depends on the frame size.
+    let mut total = 1;
+    let chunk = loop {
+        let chunk = MSG_CHANNEL::MAX_PAYLOAD - part_overhead(total);
+        let needed = max(1, payload.len().div_ceil(chunk));
+        if needed <= total {
+            break chunk;
+        }
+        total = needed;
+    };
+
+    let chunks: Vec<&[u8]> = if payload.is_empty() {
+        vec![payload]
+    } else {
+        payload.chunks(chunk).collect()
+    };
+    let total = chunks.len();
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut part = format!("<part seq=\"{}\" total=\"{}\">", i + 1, total).into_bytes();
+            part.extend_from_slice(chunk);
+            part.extend_from_slice(b"</part>");
+            part
+        })
+        .collect())
+}
+
+/// Not all parts were written before the timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartsTimeout {
+    pub sent: usize,
+    pub total: usize,
+}
+
+impl std::fmt::Display for PartsTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "only {} of {} parts were consumed in time",
+            self.sent, self.total
+        )
+    }
+}
+
+impl std::error::Error for PartsTimeout {}
+
+/// Errors returned by `send_parts`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendPartsError {
+    Nul(NulInPayload),
+    Timeout(PartsTimeout),
+}
+
+impl std::fmt::Display for SendPartsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SendPartsError::Nul(e) => e.fmt(f),
+            SendPartsError::Timeout(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SendPartsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SendPartsError::Nul(e) => Some(e),
+            SendPartsError::Timeout(e) => Some(e),
+        }
+    }
+}
+
+impl From<NulInPayload> for SendPartsError {
+    fn from(e: NulInPayload) -> Self {
+        SendPartsError::Nul(e)
+    }
+}
+
+impl From<PartsTimeout> for SendPartsError {
+    fn from(e: PartsTimeout) -> Self {
+        SendPartsError::Timeout(e)
+    }
+}
+
+/// Write the parts of `payload` to channel `c` one by one, waiting for each to be consumed by the other side.
+/// Nothing is written if the payload cannot be split.
+pub fn send_parts<A: AppChannel + ?Sized>(
+    app_channel: &A,
+    c: MsgChannel,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<(), SendPartsError> {
+    let deadline = Instant::now() + timeout;
+    let parts = split_parts(payload)?;
+    let total = parts.len();
+    for (sent, part) in parts.into_iter().enumerate() {
+        let mut part = (c, part);
+        // Parts are framed by `split_parts` and fit into the channel.
+        while let Some(rejected) = unsafe { app_channel.push_unchecked(part) } {
+            part = rejected;
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PartsTimeout { sent, total }.into());
+            }
+            thread::sleep(PART_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+    Ok(())
+}
+
+fn parse_part(part: &[u8]) -> Option<(usize, usize, &[u8])> {
+    let rest = part.strip_prefix(b"<part seq=\"")?;
+    let body = rest.strip_suffix(b"</part>")?;
+    let header_end = body.iter().position(|b| *b == b'>')?;
+    let header = std::str::from_utf8(&body[..header_end]).ok()?;
+    let (seq, total) = header.split_once("\" total=\"")?;
+    let seq = seq.parse().ok()?;
+    let total = total.strip_suffix('"')?.parse().ok()?;
+    if seq == 0 || seq > total {
+        return None;
+    }
+    Some((seq, total, &body[header_end + 1..]))
+}
+
+/// Collects parts written by `send_parts` until a payload is complete.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    buf: Vec<u8>,
+    /// Parts of the current payload received so far, 0 if none is in progress.
+    received: usize,
+    total: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a received part. Returns the payload once its last part arrived.
+    ///
+    /// Parts are expected in order. A first part always starts over, discarding an incomplete payload. Any other
+    /// part that does not continue the current payload, e.g. a duplicate, is rejected and discards it as well.
+    pub fn push_part(&mut self, part: &[u8]) -> Result<Option<Vec<u8>>, MalformedMessage> {
+        let malformed = || MalformedMessage {
+            field: "part".into(),
+            value: String::from_utf8_lossy(part).into_owned(),
+        };
+        let (seq, total, chunk) = parse_part(part).ok_or_else(malformed)?;
+        if seq == 1 {
+            self.buf.clear();
+            self.total = total;
+        } else if self.received == 0 || seq != self.received + 1 || total != self.total {
+            self.buf.clear();
+            self.received = 0;
+            return Err(malformed());
+        }
+        self.buf.extend_from_slice(chunk);
+        self.received = seq;
+
+        if seq == total {
+            self.received = 0;
+            Ok(Some(std::mem::take(&mut self.buf)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Take a part from channel `c`, if one is pending, and add it like `push_part`.
+    pub fn receive<A: AppChannel + ?Sized>(
+        &mut self,
+        app_channel: &A,
+        c: MsgChannel,
+    ) -> Result<Option<Vec<u8>>, MalformedMessage> {
+        match app_channel.receive(c) {
+            Some(part) => self.push_part(&part),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| b'a' + (i % 26) as u8).collect()
+    }
+
+    #[test]
+    fn test_split_fits_channel() {
+        assert_eq!(
+            split_parts(b""),
+            Ok(vec![b"<part seq=\"1\" total=\"1\"></part>".to_vec()])
+        );
+
+        for len in [1, MSG_CHANNEL::MAX_PAYLOAD, 10 * MSG_CHANNEL::MAX_PAYLOAD] {
+            let parts = split_parts(&payload(len)).unwrap();
+            assert!(parts.iter().all(|p| p.len() <= MSG_CHANNEL::MAX_PAYLOAD));
+
+            let mut r = Reassembler::new();
+            let mut out = None;
+            for p in &parts {
+                assert_eq!(out, None);
+                out = r.push_part(p).unwrap();
+            }
+            assert_eq!(out, Some(payload(len)));
+        }
+    }
+
+    fn three_part_round_trip(c: MsgChannel) {
+        let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+        let data = payload(2 * MSG_CHANNEL::MAX_PAYLOAD + 100);
+        assert_eq!(split_parts(&data).unwrap().len(), 3);
+
+        let sender = {
+            let app_channel = app_channel.clone();
+            let data = data.clone();
+            thread::spawn(move || send_parts(&*app_channel, c, &data, Duration::from_secs(10)))
+        };
+
+        let mut r = Reassembler::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let received = loop {
+            if let Some(v) = r.receive(&*app_channel, c).unwrap() {
+                break v;
+            }
+            assert!(Instant::now() < deadline, "payload did not arrive");
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(sender.join().unwrap(), Ok(()));
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn test_three_part_round_trip() {
+        three_part_round_trip(MsgChannel::TrickleUp);
+        three_part_round_trip(MsgChannel::TrickleDown);
+    }
+
+    #[test]
+    fn test_nul_in_payload_rejected() {
+        assert_eq!(split_parts(b"ab\0c"), Err(NulInPayload { position: 2 }));
+
+        let app_channel = MemoryAppChannel::default();
+        assert_eq!(
+            send_parts(
+                &app_channel,
+                MsgChannel::TrickleUp,
+                b"ab\0c",
+                Duration::from_secs(1)
+            ),
+            Err(SendPartsError::Nul(NulInPayload { position: 2 }))
+        );
+        assert!(app_channel.is_empty(MsgChannel::TrickleUp));
+    }
+
+    #[test]
+    fn test_parts_of_different_payloads_not_mixed() {
+        let first = split_parts(&payload(2 * MSG_CHANNEL::MAX_PAYLOAD)).unwrap();
+        let second = split_parts(&payload(2 * MSG_CHANNEL::MAX_PAYLOAD + 1)).unwrap();
+        assert_eq!(first.len(), second.len());
+
+        // A new first part starts over.
+        let mut r = Reassembler::new();
+        assert_eq!(r.push_part(&first[0]), Ok(None));
+        assert_eq!(r.push_part(&second[0]), Ok(None));
+        assert_eq!(r.push_part(&second[1]), Ok(None));
+        assert_eq!(
+            r.push_part(&second[2]),
+            Ok(Some(payload(2 * MSG_CHANNEL::MAX_PAYLOAD + 1)))
+        );
+
+        // A duplicate or a part without its predecessors is rejected and discards the incomplete payload.
+        assert_eq!(r.push_part(&first[0]), Ok(None));
+        assert_eq!(r.push_part(&first[1]), Ok(None));
+        assert!(r.push_part(&second[1]).is_err());
+        assert!(r.push_part(&first[2]).is_err());
+        assert!(r.push_part(&first[1]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_part_rejected() {
+        let mut r = Reassembler::new();
+        for part in [
+            &b"<have_new_trickle_up/>"[..],
+            b"<part seq=\"0\" total=\"2\">x</part>",
+            b"<part seq=\"3\" total=\"2\">x</part>",
+            b"<part seq=\"1\" total=\"2\">x",
+        ] {
+            assert!(r.push_part(part).is_err());
+        }
+    }
+}
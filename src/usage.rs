@@ -0,0 +1,143 @@
+use std::{fs, io, time::Instant};
+
+/// CPU time consumed by the current process (user and system), in seconds.
+pub fn process_cpu_time() -> io::Result<f64> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+    Ok(seconds(usage.ru_utime) + seconds(usage.ru_stime))
+}
+
+/// Source of the process CPU time, replaceable e.g. to simulate a sandbox where `getrusage` fails.
+pub trait CpuTimeSource: Send + Sync {
+    fn cpu_time(&self) -> io::Result<f64>;
+}
+
+/// `process_cpu_time`, backed by `getrusage`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Getrusage;
+
+impl CpuTimeSource for Getrusage {
+    fn cpu_time(&self) -> io::Result<f64> {
+        process_cpu_time()
+    }
+}
+
+/// A CPU time measurement.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuTime {
+    pub seconds: f64,
+    /// The source failed and `seconds` is the wall-clock time since the tracker was created instead.
+    pub approximate: bool,
+}
+
+/// Measures CPU time without failing: if the source is unavailable, wall-clock time is reported instead, which
+/// overestimates CPU time but keeps status reports going.
+pub struct CpuTimeTracker {
+    source: Box<dyn CpuTimeSource>,
+    started: Instant,
+}
+
+impl Default for CpuTimeTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CpuTimeTracker {
+    pub fn new() -> Self {
+        Self::with_source(Getrusage)
+    }
+
+    pub fn with_source<S: CpuTimeSource + 'static>(source: S) -> Self {
+        Self {
+            source: Box::new(source),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn cpu_time(&self) -> CpuTime {
+        match self.source.cpu_time() {
+            Ok(seconds) => CpuTime {
+                seconds,
+                approximate: false,
+            },
+            Err(_) => CpuTime {
+                seconds: self.started.elapsed().as_secs_f64(),
+                approximate: true,
+            },
+        }
+    }
+}
+
+/// Reads the resident set size of the current process, reported to the client as the working set size.
+pub struct MemoryReporter {
+    page_size: u64,
+}
+
+impl Default for MemoryReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryReporter {
+    pub fn new() -> Self {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        Self {
+            page_size: if page_size > 0 {
+                page_size as u64
+            } else {
+                4096
+            },
+        }
+    }
+
+    /// Resident memory in bytes, from `/proc/self/statm`.
+    pub fn working_set_size(&self) -> io::Result<f64> {
+        let statm = fs::read_to_string("/proc/self/statm")?;
+        let resident: u64 = statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed statm"))?;
+        Ok((resident * self.page_size) as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    struct Unavailable;
+
+    impl CpuTimeSource for Unavailable {
+        fn cpu_time(&self) -> io::Result<f64> {
+            Err(io::Error::other("getrusage is not permitted"))
+        }
+    }
+
+    #[test]
+    fn test_cpu_time_falls_back_to_wall_clock() {
+        let tracker = CpuTimeTracker::with_source(Unavailable);
+        thread::sleep(Duration::from_millis(20));
+        let t = tracker.cpu_time();
+        assert!(t.approximate);
+        assert!(t.seconds >= 0.02);
+
+        let t = CpuTimeTracker::new().cpu_time();
+        assert!(!t.approximate);
+        assert!(t.seconds >= 0.0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_working_set_size() {
+        let wss = MemoryReporter::new().working_set_size().unwrap();
+        assert!(wss > 0.0);
+    }
+}
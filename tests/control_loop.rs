@@ -0,0 +1,174 @@
+use boinc_app_api::{app::*, control_loop::*, models::*, shmem::*};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Plays the BOINC client on another thread: keeps sending heartbeats, collects the status messages of the
+/// application and forwards the process control requests it is given.
+struct TestClient {
+    requests: mpsc::Sender<ProcessControlRequest>,
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<Vec<StatusMessage>>,
+}
+
+impl TestClient {
+    fn spawn(app_channel: SharedAppChannel) -> Self {
+        let (requests, pending) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut received = Vec::new();
+                let mut request = None;
+                while !stop.load(Ordering::SeqCst) {
+                    let _ = app_channel.force_if_empty(Message::Control(
+                        ControlMessage::Heartbeat(Heartbeat {
+                            wss: None,
+                            max_wss: None,
+                        }),
+                    ));
+                    received.extend(std::iter::from_fn(|| app_channel.pull_status()));
+
+                    // One request at a time, each once the previous one has been consumed.
+                    if request.is_none() {
+                        request = pending.try_recv().ok();
+                    }
+                    if let Some(r) = request.take() {
+                        let m = Message::Control(ControlMessage::ProcessControlRequest(r));
+                        if let Some(Message::Control(ControlMessage::ProcessControlRequest(r))) =
+                            app_channel.push(m)
+                        {
+                            request = Some(r);
+                        }
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                received.extend(std::iter::from_fn(|| app_channel.pull_status()));
+                received
+            })
+        };
+        Self {
+            requests,
+            stop,
+            thread,
+        }
+    }
+
+    fn send(&self, request: ProcessControlRequest) {
+        self.requests.send(request).unwrap();
+    }
+
+    /// Stop the client and return the status messages it received.
+    fn stop(self) -> Vec<StatusMessage> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.join().unwrap()
+    }
+}
+
+fn wait_for(what: &str, cond: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !cond() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn last_fraction_done(received: &[StatusMessage]) -> Option<f64> {
+    received.iter().rev().find_map(|m| match m {
+        StatusMessage::AppStatus(v) => Some(v.fraction_done),
+        _ => None,
+    })
+}
+
+#[test]
+fn test_toy_workload_completes() {
+    let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+    let test_client = TestClient::spawn(app_channel.clone());
+    let client = AppClient::new(app_channel);
+
+    // The work takes far longer than the heartbeat timeout, so only the heartbeats keep the loop going.
+    let total = 300;
+    let steps = AtomicUsize::new(0);
+    let checkpoints = AtomicUsize::new(0);
+    let exit = ControlLoop::new(
+        &client,
+        || {
+            thread::sleep(Duration::from_millis(1));
+            if steps.fetch_add(1, Ordering::SeqCst) + 1 == total {
+                WorkStatus::Done
+            } else {
+                WorkStatus::Continue
+            }
+        },
+        || {
+            checkpoints.fetch_add(1, Ordering::SeqCst);
+        },
+        || steps.load(Ordering::SeqCst) as f64 / total as f64,
+    )
+    .status_period(Duration::from_millis(10))
+    .heartbeat_timeout(Some(Duration::from_millis(100)))
+    .run();
+
+    assert_eq!(exit, LoopExit::Finished);
+    assert_eq!(steps.load(Ordering::SeqCst), total);
+    assert_eq!(checkpoints.load(Ordering::SeqCst), 1);
+    let received = test_client.stop();
+    assert_eq!(last_fraction_done(&received), Some(1.0));
+}
+
+#[test]
+fn test_suspend_resume_and_quit() {
+    let app_channel: SharedAppChannel = Arc::new(MemoryAppChannel::default());
+    let test_client = TestClient::spawn(app_channel.clone());
+    let client = AppClient::new(app_channel);
+
+    let steps = AtomicUsize::new(0);
+    let exit = thread::scope(|s| {
+        let control_loop = s.spawn(|| {
+            ControlLoop::new(
+                &client,
+                || {
+                    steps.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(1));
+                    WorkStatus::Continue
+                },
+                || {},
+                || 0.5,
+            )
+            .poll_interval(Duration::from_millis(1))
+            .status_period(Duration::from_millis(10))
+            .heartbeat_timeout(Some(Duration::from_secs(5)))
+            .run()
+        });
+
+        wait_for("work to start", || steps.load(Ordering::SeqCst) >= 10);
+        test_client.send(ProcessControlRequest::Suspend {
+            reason: SuspendReason::USER_ACTIVE,
+        });
+        wait_for("work to stop", || {
+            let before = steps.load(Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            steps.load(Ordering::SeqCst) == before
+        });
+        let suspended_at = steps.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(steps.load(Ordering::SeqCst), suspended_at);
+
+        test_client.send(ProcessControlRequest::Resume);
+        wait_for("work to resume", || {
+            steps.load(Ordering::SeqCst) > suspended_at
+        });
+
+        test_client.send(ProcessControlRequest::Quit);
+        control_loop.join().unwrap()
+    });
+
+    assert_eq!(exit, LoopExit::Quit);
+    let received = test_client.stop();
+    assert_eq!(last_fraction_done(&received), Some(0.5));
+}
@@ -0,0 +1,38 @@
+//! The declared role is process-wide, so it gets a test binary of its own instead of racing with unit tests.
+
+use boinc_app_api::{models::*, role::*, shmem::*};
+
+#[test]
+fn test_declared_role() {
+    assert_eq!(declared_role(), None);
+    declare_role(Role::App);
+    assert_eq!(declared_role(), Some(Role::App));
+
+    // Writing in the other side's direction is only warned about, every write path still goes through.
+    let control = Message::Control(ControlMessage::ProcessControlRequest(
+        ProcessControlRequest::Quit,
+    ));
+    let c = MemoryAppChannel::default();
+    assert_eq!(c.push(control.clone()), None);
+    assert!(!c.is_empty(MsgChannel::ProcessControlRequest));
+    c.clear(MsgChannel::ProcessControlRequest);
+    assert!(c.force_if_empty(control.clone()));
+    c.force(control);
+    unsafe {
+        c.force_unchecked((MsgChannel::Heartbeat, b"<wss>1</wss>".to_vec()));
+        assert_eq!(
+            c.push_unchecked((
+                MsgChannel::TrickleDown,
+                b"<have_new_trickle_down/>".to_vec()
+            )),
+            None
+        );
+    }
+    assert!(c.is_any_pending(&[MsgChannel::Heartbeat]));
+    assert!(c.is_any_pending(&[MsgChannel::TrickleDown]));
+
+    declare_role(Role::Client);
+    assert_eq!(declared_role(), Some(Role::Client));
+    clear_role();
+    assert_eq!(declared_role(), None);
+}
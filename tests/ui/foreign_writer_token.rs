@@ -0,0 +1,15 @@
+use boinc_app_api::{models::*, role::*, shmem::*};
+use std::sync::Arc;
+
+fn main() {
+    GuardedChannel::scope(Arc::new(MemoryAppChannel::default()), 0, |first, _| {
+        GuardedChannel::scope(Arc::new(MemoryAppChannel::default()), 1, |_, tokens| {
+            first.force(
+                &tokens[0],
+                Message::Status(StatusMessage::ProcessControlReply(
+                    ProcessControlReply::Quit,
+                )),
+            );
+        });
+    });
+}
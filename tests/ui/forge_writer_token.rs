@@ -0,0 +1,16 @@
+use boinc_app_api::{models::*, role::*, shmem::*};
+use std::{marker::PhantomData, sync::Arc};
+
+fn main() {
+    GuardedChannel::scope(Arc::new(MemoryAppChannel::default()), 0, |c, _tokens| {
+        let token = WriterToken {
+            _brand: PhantomData,
+        };
+        c.force(
+            &token,
+            Message::Status(StatusMessage::ProcessControlReply(
+                ProcessControlReply::Quit,
+            )),
+        );
+    });
+}
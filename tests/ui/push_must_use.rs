@@ -0,0 +1,10 @@
+#![deny(unused_must_use)]
+
+use boinc_app_api::{models::*, shmem::*};
+
+fn main() {
+    let c = MemoryAppChannel::default();
+    c.push(Message::Status(StatusMessage::ProcessControlReply(
+        ProcessControlReply::Quit,
+    )));
+}